@@ -0,0 +1,92 @@
+use serde_json::{
+    Value,
+    Map,
+};
+use std::cmp::Ordering;
+
+
+/// Reads the records from a data file, picking the format from its extension: `.json`, `.toml`, or
+/// `.csv`.
+///
+/// The file must hold a list of records. A JSON or TOML file can also hold a table with a single
+/// list in it, since TOML can't have a list at the top level.
+pub fn parse(file:&str,contents:&str)->Result<Vec<Value>,String> {
+    let extension=file.rsplit_once('.').map(|(_,e)|e.to_ascii_lowercase()).unwrap_or_default();
+    let value=match extension.as_str() {
+        "json"=>serde_json::from_str::<Value>(contents).map_err(|e|e.to_string())?,
+        "toml"=>toml::from_str::<Value>(contents).map_err(|e|e.to_string())?,
+        "csv"=>return csv_records(contents),
+        _=>return Err("Expected a `.json`, `.toml`, or `.csv` file".to_string()),
+    };
+    match value {
+        Value::Array(records)=>Ok(records),
+        Value::Object(map) if map.len()==1=>match map.into_iter().next() {
+            Some((_,Value::Array(records)))=>Ok(records),
+            _=>Err("Expected a list of records".to_string()),
+        },
+        _=>Err("Expected a list of records".to_string()),
+    }
+}
+/// Each row after the header is a record, with the header's names as its fields.
+fn csv_records(contents:&str)->Result<Vec<Value>,String> {
+    let mut reader=csv::Reader::from_reader(contents.as_bytes());
+    let headers=reader.headers().map_err(|e|e.to_string())?.clone();
+    let mut records=Vec::new();
+    for row in reader.records() {
+        let row=row.map_err(|e|e.to_string())?;
+        let mut record=Map::new();
+        for (name,value) in headers.iter().zip(row.iter()) {
+            record.insert(name.to_string(),Value::String(value.to_string()));
+        }
+        records.push(Value::Object(record));
+    }
+    return Ok(records);
+}
+/// Looks up a field by a path like `links.home`.
+pub fn field<'a>(record:&'a Value,path:&str)->Option<&'a Value> {
+    let mut value=record;
+    for name in path.split('.') {
+        value=match value {
+            Value::Object(map)=>map.get(name)?,
+            Value::Array(list)=>list.get(name.parse::<usize>().ok()?)?,
+            _=>return None,
+        };
+    }
+    return Some(value);
+}
+/// The text a value is written as in a document. Lists are joined with commas.
+pub fn text(value:&Value)->String {
+    match value {
+        Value::Null=>String::new(),
+        Value::String(s)=>s.clone(),
+        Value::Array(list)=>list.iter().map(text).collect::<Vec<_>>().join(", "),
+        _=>value.to_string(),
+    }
+}
+/// Whether a `where` with only a field keeps the record. Missing fields, `null`, `false`, `0`,
+/// and empty text and lists are false.
+pub fn truthy(value:Option<&Value>)->bool {
+    match value {
+        None|Some(Value::Null)=>false,
+        Some(Value::Bool(b))=>*b,
+        Some(Value::Number(n))=>n.as_f64()!=Some(0.0),
+        Some(Value::String(s))=>!s.is_empty(),
+        Some(Value::Array(list))=>!list.is_empty(),
+        Some(Value::Object(_))=>true,
+    }
+}
+/// Compares numerically when both values are numbers, even as text like CSV fields, and by text
+/// otherwise.
+pub fn compare(a:&Value,b:&Value)->Ordering {
+    match (number(a),number(b)) {
+        (Some(a),Some(b))=>a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _=>text(a).cmp(&text(b)),
+    }
+}
+fn number(value:&Value)->Option<f64> {
+    match value {
+        Value::Number(n)=>n.as_f64(),
+        Value::String(s)=>s.trim().parse().ok(),
+        _=>None,
+    }
+}
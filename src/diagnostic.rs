@@ -0,0 +1,334 @@
+use std::{
+    env::var_os,
+    io::{
+        stderr,
+        IsTerminal,
+    },
+};
+use serde_json::json;
+use crate::{
+    parser::{
+        Label,
+        SourceError,
+    },
+    sources::Sources,
+    warning::{
+        Level,
+        Levels,
+        Warning,
+    },
+};
+
+
+/// Bold red, for `error`.
+const ERROR_COLOR:&str="1;31";
+/// Bold yellow, for `warning`.
+const WARNING_COLOR:&str="1;33";
+/// Bold blue, for the gutter and the labels.
+const NOTE_COLOR:&str="1;34";
+const BOLD:&str="1";
+/// What ends a word that gets underlined, besides whitespace.
+const WORD_ENDS:&[char]=&['{','}','(',')','[',']',':',',','"'];
+
+
+/// How errors are printed.
+#[derive(Copy,Clone,PartialEq)]
+pub enum ErrorFormat {
+    /// Like rustc, with the lines the error is on underlined, in color if `color` is set.
+    Human {
+        color:bool,
+    },
+    /// One JSON object per line, for editors and other tools.
+    Json,
+}
+impl ErrorFormat {
+    /// The format for `--error-format`, with color picked by `--color`.
+    pub fn from_names(format:&str,color:&str)->Option<Self> {
+        let color=match color {
+            "auto"=>stderr().is_terminal()&&var_os("NO_COLOR").is_none(),
+            "always"=>true,
+            "never"=>false,
+            _=>return None,
+        };
+        match format {
+            "human"=>Some(ErrorFormat::Human{color}),
+            "json"=>Some(ErrorFormat::Json),
+            _=>None,
+        }
+    }
+    pub fn print(&self,diagnostic:&Diagnostic) {
+        match self {
+            // A blank line between diagnostics, like rustc.
+            ErrorFormat::Human{color}=>eprintln!("{}",render(diagnostic,*color)),
+            ErrorFormat::Json=>eprintln!("{}",to_json(diagnostic)),
+        }
+    }
+}
+impl Default for ErrorFormat {
+    fn default()->Self {
+        ErrorFormat::from_names("human","auto").unwrap()
+    }
+}
+
+
+/// How problems are reported while building.
+#[derive(Clone,Default)]
+pub struct Report {
+    pub format:ErrorFormat,
+    pub levels:Levels,
+    /// Only report what fails the build.
+    pub quiet:bool,
+}
+impl Report {
+    pub fn error(&self,error:&SourceError) {
+        self.format.print(&Diagnostic::error(error));
+    }
+    /// Prints the warnings that aren't allowed, leaving out the ones that don't fail the build when
+    /// `quiet` is set. Returns whether any are denied.
+    pub fn warnings(&self,warnings:&[Warning],sources:&Sources)->bool {
+        let mut denied=false;
+        for warning in warnings {
+            let level=self.levels.level(&warning.kind);
+            denied|=level==Level::Deny;
+            if level==Level::Deny||(level==Level::Warn&&!self.quiet) {
+                self.format.print(&Diagnostic::warning(warning,level,sources));
+            }
+        }
+        return denied;
+    }
+}
+
+
+#[derive(Copy,Clone,PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+impl Severity {
+    fn name(&self)->&'static str {
+        match self {
+            Severity::Error=>"error",
+            Severity::Warning=>"warning",
+        }
+    }
+}
+/// An error or a warning, as it gets printed.
+pub struct Diagnostic<'a> {
+    pub severity:Severity,
+    /// The name of the warning it is, if it is one.
+    pub code:Option<&'static str>,
+    pub message:String,
+    /// The file, its contents, and where it is in them, which is [`None`] for the end. Warnings
+    /// about the whole document have none.
+    pub span:Option<(&'a str,&'a str,Option<usize>)>,
+    pub labels:&'a [Label<'a>],
+    pub help:Option<&'a str>,
+    pub note:Option<String>,
+}
+impl<'a> Diagnostic<'a> {
+    pub fn error(error:&'a SourceError<'a>)->Self {
+        Diagnostic {
+            severity:Severity::Error,
+            code:None,
+            message:error.kind.to_string(),
+            span:Some((error.file,error.source,error.offset)),
+            labels:&error.labels,
+            help:error.help.as_deref(),
+            note:None,
+        }
+    }
+    /// A warning at the level it's reported at, with its location looked up in `sources`.
+    pub fn warning(warning:&'a Warning,level:Level,sources:&'a Sources)->Self {
+        let span=warning.location.as_ref().and_then(|location|{
+            let source=sources.get(&location.file)?;
+            Some((location.file.as_str(),source,Some(location.offset)))
+        });
+        let denied=level==Level::Deny;
+        Diagnostic {
+            severity:if denied {Severity::Error} else {Severity::Warning},
+            code:Some(warning.kind.name()),
+            message:warning.kind.to_string(),
+            span,
+            labels:&[],
+            help:None,
+            note:denied.then(||format!("`{}` is denied. Use `--allow {}` to ignore it",warning.kind.name(),warning.kind.name())),
+        }
+    }
+}
+
+
+/// A place in a file to point at: a line and column, both from 1, and how many characters wide.
+struct Position<'a> {
+    line:usize,
+    column:usize,
+    width:usize,
+    /// The text of the line, and what goes before the column, with tabs kept so the underline
+    /// lines up.
+    text:&'a str,
+    indent:String,
+}
+impl<'a> Position<'a> {
+    /// Where `offset` is in `source`. [`None`] is the end of it.
+    fn new(source:&'a str,offset:Option<usize>)->Self {
+        let offset=offset.unwrap_or_else(||source.trim_end().len()).min(source.len());
+        let start=source[..offset].rfind('\n').map(|i|i+1).unwrap_or(0);
+        let end=source[offset..].find('\n').map(|i|offset+i).unwrap_or(source.len());
+        let before=&source[start..offset];
+        let rest=&source[offset..end];
+        Position {
+            line:source[..offset].matches('\n').count()+1,
+            column:before.chars().count()+1,
+            width:rest[..word_len(rest)].chars().count().max(1),
+            text:source[start..end].trim_end_matches('\r'),
+            indent:before.chars().map(|c|if c=='\t' {'\t'} else {' '}).collect(),
+        }
+    }
+}
+/// How many bytes of `rest` to underline: the word it starts with, or its first character if that
+/// is punctuation.
+pub fn word_len(rest:&str)->usize {
+    match rest.chars().next() {
+        Some(c) if WORD_ENDS.contains(&c)=>c.len_utf8(),
+        _=>rest.find(|c:char|c.is_whitespace()||WORD_ENDS.contains(&c)).unwrap_or(rest.len()),
+    }
+}
+/// A part of the source to underline, with `mark`, and what to say about it.
+struct Span<'a> {
+    file:&'a str,
+    position:Position<'a>,
+    mark:char,
+    message:Option<&'a str>,
+}
+
+
+/// Renders a diagnostic like rustc does: the message, where it is, and the lines it points at
+/// with the parts underlined. Ends with a newline.
+pub fn render(diagnostic:&Diagnostic,color:bool)->String {
+    let paint=|text:&str,code:&str|if color {format!("\x1b[{}m{}\x1b[0m",code,text)} else {text.to_string()};
+    let mut spans=Vec::new();
+    if let Some((file,source,offset))=diagnostic.span {
+        spans.push(Span{file,position:Position::new(source,offset),mark:'^',message:None});
+    }
+    for label in diagnostic.labels.iter() {
+        spans.push(Span{file:label.file,position:Position::new(label.source,Some(label.offset)),mark:'-',message:Some(&label.message)});
+    }
+    let gutter=spans.iter().map(|s|s.position.line.to_string().len()).max().unwrap_or(1);
+    let pad=" ".repeat(gutter);
+    let bar=paint("|",NOTE_COLOR);
+    let severity_color=match diagnostic.severity {
+        Severity::Error=>ERROR_COLOR,
+        Severity::Warning=>WARNING_COLOR,
+    };
+    let severity=match diagnostic.code {
+        Some(code)=>format!("{}[{}]",diagnostic.severity.name(),code),
+        None=>diagnostic.severity.name().to_string(),
+    };
+    let mut out=format!("{}{}\n",paint(&severity,severity_color),paint(&format!(": {}",diagnostic.message),BOLD));
+    // The spans in the diagnostic's file come first, then each other file's, each in line order.
+    let mut files=Vec::new();
+    for span in spans.iter() {
+        if !files.contains(&span.file) {
+            files.push(span.file);
+        }
+    }
+    for (i,file) in files.iter().enumerate() {
+        let mut in_file=spans.iter().filter(|s|s.file==*file).collect::<Vec<_>>();
+        let first=&in_file[0].position;
+        let arrow=if i==0 {"-->"} else {":::"};
+        out.push_str(&format!("{}{} {}:{}:{}\n",pad,paint(arrow,NOTE_COLOR),file,first.line,first.column));
+        out.push_str(&format!("{} {}\n",pad,bar));
+        in_file.sort_by_key(|s|(s.position.line,s.position.column));
+        let mut last_line=None;
+        for span in in_file {
+            let position=&span.position;
+            if last_line!=Some(position.line) {
+                if last_line.is_some_and(|l|position.line>l+1) {
+                    out.push_str(&format!("{}\n",paint("...",NOTE_COLOR)));
+                }
+                let number=format!("{:>width$}",position.line,width=gutter);
+                out.push_str(&format!("{} {} {}\n",paint(&number,NOTE_COLOR),bar,position.text));
+                last_line=Some(position.line);
+            }
+            let code=if span.mark=='^' {severity_color} else {NOTE_COLOR};
+            let mut underline=span.mark.to_string().repeat(position.width);
+            if let Some(message)=span.message {
+                underline=format!("{} {}",underline,message);
+            }
+            out.push_str(&format!("{} {} {}{}\n",pad,bar,position.indent,paint(&underline,code)));
+        }
+    }
+    let notes=[("note",diagnostic.note.as_deref()),("help",diagnostic.help)];
+    for (i,(name,text)) in notes.into_iter().filter_map(|(name,text)|Some((name,text?))).enumerate() {
+        if i==0&&!spans.is_empty() {
+            out.push_str(&format!("{} {}\n",pad,bar));
+        }
+        out.push_str(&format!("{} {} {}: {}\n",pad,paint("=",NOTE_COLOR),paint(name,BOLD),text));
+    }
+    return out;
+}
+/// The diagnostic as one line of JSON, with lines and columns counted from 1.
+pub fn to_json(diagnostic:&Diagnostic)->String {
+    let labels=diagnostic.labels.iter().map(|label|{
+        let at=Position::new(label.source,Some(label.offset));
+        json!({
+            "file":label.file,
+            "line":at.line,
+            "column":at.column,
+            "width":at.width,
+            "message":label.message,
+        })
+    }).collect::<Vec<_>>();
+    let mut json=json!({
+        "severity":diagnostic.severity.name(),
+        "code":diagnostic.code,
+        "message":diagnostic.message,
+        "labels":labels,
+        "help":diagnostic.help,
+        "note":diagnostic.note,
+    });
+    if let Some((file,source,offset))=diagnostic.span {
+        let position=Position::new(source,offset);
+        json["file"]=json!(file);
+        json["line"]=json!(position.line);
+        json["column"]=json!(position.column);
+        json["width"]=json!(position.width);
+    }
+    return json.to_string();
+}
+/// Suggests the candidate closest to `name`, if any is close enough to be a likely typo.
+pub fn did_you_mean<'a>(name:&str,candidates:impl IntoIterator<Item=&'a str>)->Option<String> {
+    closest(name,candidates).map(|candidate|format!("did you mean `{}`?",candidate))
+}
+fn closest<'a>(name:&str,candidates:impl IntoIterator<Item=&'a str>)->Option<&'a str> {
+    let most=(name.chars().count()/3).max(1);
+    return candidates.into_iter()
+        .map(|candidate|(edit_distance(name,candidate),candidate))
+        .filter(|(distance,_)|*distance<=most)
+        .min_by_key(|(distance,_)|*distance)
+        .map(|(_,candidate)|candidate);
+}
+/// How many characters have to be inserted, removed, replaced, or swapped with the next one to
+/// turn `a` into `b`.
+fn edit_distance(a:&str,b:&str)->usize {
+    let a=a.chars().collect::<Vec<_>>();
+    let b=b.chars().collect::<Vec<_>>();
+    // distances[i][j] is the distance between the first i characters of `a` and the first j of `b`.
+    let mut distances=vec![vec![0;b.len()+1];a.len()+1];
+    for (i,row) in distances.iter_mut().enumerate() {
+        row[0]=i;
+    }
+    for (j,distance) in distances[0].iter_mut().enumerate() {
+        *distance=j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let replace=distances[i-1][j-1]+if a[i-1]==b[j-1] {0} else {1};
+            let mut distance=replace.min(distances[i-1][j]+1).min(distances[i][j-1]+1);
+            if i>1&&j>1&&a[i-1]==b[j-2]&&a[i-2]==b[j-1] {
+                distance=distance.min(distances[i-2][j-2]+1);
+            }
+            distances[i][j]=distance;
+        }
+    }
+    return distances[a.len()][b.len()];
+}
@@ -0,0 +1,278 @@
+use crate::{
+    parser::{
+        DocumentParser,
+        Parser,
+        Var,
+        PAGE_STYLE_KEYS,
+        SECTION_STYLE_KEYS,
+    },
+    sources::Sources,
+    syntax::{
+        key,
+        BlockKind,
+        Body,
+        Node,
+        SyntaxTree,
+    },
+};
+
+
+const INDENT:&str="    ";
+const SIZE_KEYS:&[&str]=&[
+    "width","height","font_size","margin","margin_left","margin_right","margin_top","margin_bottom",
+];
+const COLOR_KEYS:&[&str]=&["text_color","background_color"];
+
+
+/// Formats a document: each block's lines are indented one level more than the block, with its
+/// `}` on a line of its own, and `key:value` is spaced as `key: value`. Content blocks keep their
+/// lines, indented to match. The keys of style blocks are put in the order the parser lists them,
+/// and sizes and colors are written the way [`Var::text`] writes them. Returns [`None`] when the
+/// braces don't match, since there is no telling what was meant.
+pub fn format(source:&str)->Option<String> {
+    let tree=SyntaxTree::new(source);
+    if !tree.is_balanced() {
+        return None;
+    }
+    let mut out=String::new();
+    format_nodes(&tree.nodes,None,0,&mut out);
+    return Some(out);
+}
+fn format_nodes(nodes:&[Node],parent:Option<BlockKind>,depth:usize,out:&mut String) {
+    let mut nodes=nodes.iter().collect::<Vec<_>>();
+    let order=match parent {
+        Some(BlockKind::SectionStyle|BlockKind::NamedStyle)=>Some(SECTION_STYLE_KEYS),
+        Some(BlockKind::PageStyle)=>Some(PAGE_STYLE_KEYS),
+        _=>None,
+    };
+    if let Some(order)=order {
+        nodes=sort_keys(nodes,order);
+    }
+    let indent=INDENT.repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Line(text,_)=>{
+                out.push_str(&indent);
+                out.push_str(&format_line(text,parent));
+                out.push('\n');
+            },
+            Node::Block(block)=>{
+                out.push_str(&indent);
+                let header=format_header(block.header);
+                if !header.is_empty() {
+                    out.push_str(&header);
+                    out.push(' ');
+                }
+                out.push_str("{\n");
+                match &block.body {
+                    Body::Nodes(children)=>format_nodes(children,Some(block.kind),depth+1,out),
+                    Body::Content(lines)=>{
+                        for line in lines {
+                            if !line.is_empty() {
+                                out.push_str(&indent);
+                                out.push_str(INDENT);
+                                out.push_str(line);
+                            }
+                            out.push('\n');
+                        }
+                    },
+                }
+                out.push_str(&indent);
+                out.push_str("}\n");
+            },
+            // A comment after something stays on its line.
+            Node::Comment(text,_,true) if out.ends_with('\n')=>{
+                out.pop();
+                out.push(' ');
+                out.push_str(&comment_lines(text));
+                out.push('\n');
+            },
+            Node::Comment(text,..)=>{
+                out.push_str(&indent);
+                out.push_str(&comment_lines(text));
+                out.push('\n');
+            },
+            Node::Blank=>out.push('\n'),
+        }
+    }
+}
+/// Puts the keys of a style block in the order of `order`. Keys can't be given twice, so the
+/// order doesn't change what the style means. Unknown keys go last, in the order they were in.
+/// Comments move with the key after them, or with the key before them when they are on its line.
+fn sort_keys<'n,'a>(nodes:Vec<&'n Node<'a>>,order:&[&str])->Vec<&'n Node<'a>> {
+    // The comments on the line of the block's `{` stay first.
+    let head=nodes.iter().take_while(|node|matches!(node,Node::Comment(_,_,true))).count();
+    let mut groups=vec![nodes[..head].to_vec()];
+    let mut leading=Vec::new();
+    for node in nodes.into_iter().skip(head) {
+        match node {
+            Node::Blank=>{},
+            Node::Comment(_,_,true) if leading.is_empty()=>groups.last_mut().unwrap().push(node),
+            Node::Comment(..)=>leading.push(node),
+            _=>{
+                leading.push(node);
+                groups.push(std::mem::take(&mut leading));
+            },
+        }
+    }
+    // Comments at the end of the block stay last.
+    groups.push(leading);
+    let tail=groups.len()-1;
+    let mut groups=groups.into_iter().enumerate().collect::<Vec<_>>();
+    groups.sort_by_key(|(i,group)|{
+        let key=group.iter().find_map(|node|match node {
+            Node::Line(text,_)=>Some(key(text)),
+            Node::Block(block)=>Some(key(block.header)),
+            _=>None,
+        });
+        match key {
+            _ if *i==0=>0,
+            _ if *i==tail=>order.len()+2,
+            Some(key)=>order.iter().position(|k|*k==key).unwrap_or(order.len())+1,
+            None=>order.len()+1,
+        }
+    });
+    return groups.into_iter().flat_map(|(_,group)|group).collect();
+}
+/// A comment's lines, without the `\r` of Windows line breaks.
+fn comment_lines(text:&str)->String {
+    return text.lines().collect::<Vec<_>>().join("\n");
+}
+/// Puts one space after the `:` of a key. Sizes and colors are normalized, but other values are
+/// left alone, since text values keep their spacing.
+fn format_line(text:&str,parent:Option<BlockKind>)->String {
+    let key=key(text);
+    let value=match text[key.len()..].strip_prefix(':') {
+        Some(value) if !key.is_empty()=>value.trim_start_matches(' '),
+        _=>return text.to_string(),
+    };
+    if value.is_empty() {
+        return format!("{}:",key);
+    }
+    let value=normalize(key,value,parent).unwrap_or_else(||value.to_string());
+    return format!("{}: {}",key,value);
+}
+/// The value written the canonical way, like `#aabbcc` or `0.5in`, when it is a size or color.
+/// Values that don't parse, like variables, are [`None`] and kept as they are.
+fn normalize(key:&str,value:&str,parent:Option<BlockKind>)->Option<String> {
+    use BlockKind::*;
+    let sources=Sources::default();
+    let mut parser=DocumentParser::new(value,"",&sources);
+    let var=match parent? {
+        Vars if value.starts_with('#')=>Var::Color(parser.color().ok()?),
+        Vars=>Var::Size(parser.size().ok()?),
+        SectionStyle|NamedStyle|PageStyle|PageSize if SIZE_KEYS.contains(&key)=>Var::Size(parser.size().ok()?),
+        SectionStyle|NamedStyle|PageStyle if COLOR_KEYS.contains(&key)=>Var::Color(parser.color().ok()?),
+        _=>return None,
+    };
+    if !parser.is_eof() {
+        return None;
+    }
+    return Some(var.text());
+}
+/// Collapses the spaces in a block header to one, outside of strings and comments.
+fn format_header(header:&str)->String {
+    let mut out=String::new();
+    let mut string=false;
+    let mut comment=false;
+    let mut escaped=false;
+    for (i,c) in header.char_indices() {
+        if comment {
+            comment^=header[..i+c.len_utf8()].ends_with("*/");
+        } else if escaped {
+            escaped=false;
+        } else if string&&c=='\\' {
+            escaped=true;
+        } else if c=='"' {
+            string^=true;
+        } else if !string&&header[i..].starts_with("/*") {
+            comment=true;
+        } else if !string&&c.is_whitespace() {
+            if !out.ends_with(' ') {
+                out.push(' ');
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    return out;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Document;
+
+    fn parse(source:&str)->Document {
+        let sources=Sources::default();
+        let (doc,errors,..)=DocumentParser::new(source,"",&sources).into_document();
+        assert!(errors.is_empty(),"`{}` doesn't parse",source);
+        return doc;
+    }
+
+    const DOCUMENT:&str="metadata {
+  title:Example // The title
+  vars {
+    gutter:0.50in
+  }
+  style sidebar {
+    text_color:#AABBCC
+    width:30%
+  }
+}
+page {
+  horizontal {
+    section {
+      content {
+        # Heading
+          Indented
+      }
+      style {
+        use:sidebar
+      }
+    }
+  }
+}
+";
+
+    #[test]
+    fn sorts_style_keys_with_their_comments() {
+        let source="page {\n    section {\n        style { // Head\n            // About the margin\n            margin: 1in\n            height: 2in // About the height\n            /* About the width */\n            width: 3in\n            // At the end\n        }\n    }\n}\n";
+        let expected="page {\n    section {\n        style { // Head\n            /* About the width */\n            width: 3in\n            height: 2in // About the height\n            // About the margin\n            margin: 1in\n            // At the end\n        }\n    }\n}\n";
+        assert_eq!(format(source).unwrap(),expected);
+    }
+
+    #[test]
+    fn keeps_comments_after_text_values() {
+        let source="metadata {\ntitle:a // b\nfont:c{d} /* e */\n}\n";
+        let expected="metadata {\n    title: a // b\n    font: c{d} /* e */\n}\n";
+        assert_eq!(format(source).unwrap(),expected);
+    }
+
+    #[test]
+    fn indents_content_blocks() {
+        let source="page {\nsection {\ncontent {\n  # Title\n\n    Code\n  Text\n}\n}\n}\n";
+        let expected="page {\n    section {\n        content {\n            # Title\n\n              Code\n            Text\n        }\n    }\n}\n";
+        assert_eq!(format(source).unwrap(),expected);
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let formatted=format(DOCUMENT).unwrap();
+        assert_eq!(format(&formatted).unwrap(),formatted);
+    }
+
+    #[test]
+    fn keeps_the_document() {
+        let formatted=format(DOCUMENT).unwrap();
+        assert_ne!(formatted,DOCUMENT);
+        assert_eq!(parse(&formatted),parse(DOCUMENT));
+    }
+
+    #[test]
+    fn leaves_unbalanced_braces() {
+        assert_eq!(format("page {\n    section {\n}\n"),None);
+        assert_eq!(format("page {\n}\n}\n"),None);
+    }
+}
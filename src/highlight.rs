@@ -0,0 +1,100 @@
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{
+        Theme,
+        ThemeSet,
+    },
+    html::{
+        ClassedHTMLGenerator,
+        ClassStyle,
+        IncludeBackground,
+        css_for_theme_with_class_style,
+        styled_line_to_highlighted_html,
+    },
+    parsing::{
+        SyntaxDefinition,
+        SyntaxSet,
+    },
+    util::LinesWithEndings,
+};
+use crate::parser::CodeHighlight;
+use std::cell::OnceCell;
+
+
+pub const DEFAULT_THEME:&str="InspiredGitHub";
+/// The themes bundled with syntect, in the order `ThemeSet::load_defaults` lists them.
+pub const THEMES:&[&str]=&[
+    "InspiredGitHub",
+    "Solarized (dark)",
+    "Solarized (light)",
+    "base16-eighties.dark",
+    "base16-mocha.dark",
+    "base16-ocean.dark",
+    "base16-ocean.light",
+];
+const TOML_SYNTAX:&str=include_str!("../syntaxes/TOML.sublime-syntax");
+/// Prefixed so the theme's classes can't collide with the renderer's own, like `.page`.
+const CLASS_STYLE:ClassStyle=ClassStyle::SpacedPrefixed{prefix:"hl-"};
+
+
+/// Highlights fenced code blocks using the syntax definitions and themes bundled with syntect, so
+/// no network access or external files are needed.
+pub struct Highlighter {
+    /// Building the syntax set is slow, so it only happens once a code block needs it.
+    syntaxes:OnceCell<SyntaxSet>,
+    theme:Theme,
+    mode:CodeHighlight,
+}
+impl Highlighter {
+    pub fn new(theme:Option<&str>,mode:CodeHighlight)->Self {
+        let mut themes=ThemeSet::load_defaults();
+        let theme=themes.themes.remove(theme.unwrap_or(DEFAULT_THEME))
+            .expect("Theme names are checked by the parser");
+        return Highlighter{syntaxes:OnceCell::new(),theme,mode};
+    }
+    fn syntaxes(&self)->&SyntaxSet {
+        self.syntaxes.get_or_init(||{
+            let mut builder=SyntaxSet::load_defaults_newlines().into_builder();
+            builder.add(SyntaxDefinition::load_from_str(TOML_SYNTAX,true,None).expect("The bundled TOML syntax is valid"));
+            builder.build()
+        })
+    }
+    /// The stylesheet needed for class based highlighting.
+    pub fn css(&self)->Option<String> {
+        if self.mode==CodeHighlight::Classes {
+            return css_for_theme_with_class_style(&self.theme,CLASS_STYLE).ok();
+        }
+        return None;
+    }
+    /// Returns the opening `<pre>` tag and highlighted contents of a code block, or `None` when the
+    /// language is not known.
+    pub fn highlight(&self,lang:&str,code:&str)->Option<(String,String)> {
+        let syntaxes=self.syntaxes();
+        let syntax=syntaxes.find_syntax_by_token(lang)?;
+        match self.mode {
+            CodeHighlight::Inline=>{
+                let mut style=String::from("white-space:break-spaces;");
+                if let Some(c)=self.theme.settings.background {
+                    style.push_str(&format!("background-color:#{:02X}{:02X}{:02X};",c.r,c.g,c.b));
+                }
+                if let Some(c)=self.theme.settings.foreground {
+                    style.push_str(&format!("color:#{:02X}{:02X}{:02X};",c.r,c.g,c.b));
+                }
+                let mut highlighter=HighlightLines::new(syntax,&self.theme);
+                let mut out=String::new();
+                for line in LinesWithEndings::from(code) {
+                    let ranges=highlighter.highlight_line(line,syntaxes).ok()?;
+                    out.push_str(&styled_line_to_highlighted_html(&ranges,IncludeBackground::No).ok()?);
+                }
+                return Some((format!("<pre style=\"{}\">",style),out));
+            },
+            CodeHighlight::Classes=>{
+                let mut generator=ClassedHTMLGenerator::new_with_class_style(syntax,syntaxes,CLASS_STYLE);
+                for line in LinesWithEndings::from(code) {
+                    generator.parse_html_for_line_which_includes_newline(line).ok()?;
+                }
+                return Some(("<pre class=\"hl-code\" style=\"white-space:break-spaces\">".to_string(),generator.finalize()));
+            },
+        }
+    }
+}
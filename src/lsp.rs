@@ -0,0 +1,587 @@
+use std::{
+    collections::HashMap,
+    io::{
+        BufRead,
+        Error as IoError,
+        ErrorKind as IoErrorKind,
+        Result as IoResult,
+        Write,
+        stdin,
+        stdout,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use serde_json::{
+    json,
+    Value,
+};
+use crate::{
+    diagnostic::{
+        word_len,
+        Diagnostic,
+        Severity,
+    },
+    format::format,
+    outline::Outline,
+    parser::*,
+    sources::Sources,
+    syntax::{
+        key,
+        Block,
+        BlockKind,
+        Body,
+        Node,
+        SyntaxTree,
+    },
+    url::percent_decode,
+    warning::{
+        Level,
+        Warning,
+        WarningKind,
+    },
+};
+
+
+/// What can start something at the top level of a file.
+const TOP_LEVEL_KEYS:&[&str]=&["metadata","page","include","component"];
+/// JSON-RPC error codes.
+const PARSE_ERROR:i64=-32700;
+const INVALID_REQUEST:i64=-32600;
+const METHOD_NOT_FOUND:i64=-32601;
+/// LSP diagnostic severities.
+const SEVERITY_ERROR:u8=1;
+const SEVERITY_WARNING:u8=2;
+/// LSP completion item kinds.
+const COMPLETION_FUNCTION:u8=3;
+const COMPLETION_PROPERTY:u8=10;
+const COMPLETION_KEYWORD:u8=14;
+/// LSP symbol kinds.
+const SYMBOL_MODULE:u8=2;
+const SYMBOL_OBJECT:u8=19;
+
+
+/// Runs a language server over stdin and stdout until the client says to exit. Returns whether it
+/// was shut down first, like the protocol asks.
+pub fn run()->IoResult<bool> {
+    let mut input=stdin().lock();
+    let mut out=stdout().lock();
+    let mut server=Server::default();
+    while let Some(body)=read_message(&mut input)? {
+        let message=match serde_json::from_slice::<Value>(&body) {
+            Ok(message)=>message,
+            Err(e)=>{
+                write_message(&mut out,&json!({"jsonrpc":"2.0","id":null,"error":{"code":PARSE_ERROR,"message":e.to_string()}}))?;
+                continue;
+            },
+        };
+        let method=message["method"].as_str().unwrap_or("");
+        let params=&message["params"];
+        let Some(id)=message.get("id") else {
+            if method=="exit" {
+                return Ok(server.shutdown);
+            }
+            server.notification(method,params,&mut out)?;
+            continue;
+        };
+        let response=match server.request(method,params) {
+            Ok(result)=>json!({"jsonrpc":"2.0","id":id,"result":result}),
+            Err((code,message))=>json!({"jsonrpc":"2.0","id":id,"error":{"code":code,"message":message}}),
+        };
+        write_message(&mut out,&response)?;
+    }
+    return Ok(false);
+}
+/// Reads the body of the next message, or [`None`] once the input ends.
+fn read_message(input:&mut impl BufRead)->IoResult<Option<Vec<u8>>> {
+    let mut length=None;
+    loop {
+        let mut line=String::new();
+        if input.read_line(&mut line)?==0 {
+            return Ok(None);
+        }
+        let line=line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name,value))=line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length=value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length)=length else {
+        return Err(IoError::new(IoErrorKind::InvalidData,"Expected a Content-Length header"));
+    };
+    let mut body=vec![0;length];
+    input.read_exact(&mut body)?;
+    return Ok(Some(body));
+}
+fn write_message(out:&mut impl Write,message:&Value)->IoResult<()> {
+    let body=message.to_string();
+    write!(out,"Content-Length: {}\r\n\r\n{}",body.len(),body)?;
+    return out.flush();
+}
+
+
+/// The open documents, by URI.
+#[derive(Default)]
+struct Server {
+    documents:HashMap<String,String>,
+    shutdown:bool,
+}
+impl Server {
+    fn request(&mut self,method:&str,params:&Value)->Result<Value,(i64,String)> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST,"The server is shut down".to_string()));
+        }
+        if method=="initialize" {
+            return Ok(json!({
+                "capabilities":{
+                    "textDocumentSync":{"openClose":true,"change":1,"save":true},
+                    "completionProvider":{},
+                    "hoverProvider":true,
+                    "foldingRangeProvider":true,
+                    "documentSymbolProvider":true,
+                    "documentFormattingProvider":true,
+                },
+                "serverInfo":{"name":env!("CARGO_PKG_NAME"),"version":env!("CARGO_PKG_VERSION")},
+            }));
+        } else if method=="shutdown" {
+            self.shutdown=true;
+            return Ok(Value::Null);
+        }
+        let handler:fn(&str,usize)->Value=match method {
+            "textDocument/completion"=>completion,
+            "textDocument/hover"=>hover,
+            "textDocument/foldingRange"=>|text,_|folding_ranges(text),
+            "textDocument/documentSymbol"=>|text,_|document_symbols(text),
+            "textDocument/formatting"=>|text,_|formatting(text),
+            _=>return Err((METHOD_NOT_FOUND,format!("Unknown method `{}`",method))),
+        };
+        let uri=params["textDocument"]["uri"].as_str().unwrap_or("");
+        return match self.documents.get(uri) {
+            Some(text)=>Ok(handler(text,offset(text,&params["position"]))),
+            None=>Ok(Value::Null),
+        };
+    }
+    /// Handles a message that gets no response. Unknown ones are ignored, like the protocol asks.
+    fn notification(&mut self,method:&str,params:&Value,out:&mut impl Write)->IoResult<()> {
+        let uri=params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen"=>{
+                let text=params["textDocument"]["text"].as_str().unwrap_or("").to_string();
+                self.documents.insert(uri.clone(),text);
+                self.publish(&uri,out)?;
+            },
+            "textDocument/didChange"=>{
+                // Changes are always the whole text, since that is the sync asked for.
+                let Some(text)=params["contentChanges"].as_array().and_then(|c|c.last()).and_then(|c|c["text"].as_str()) else {
+                    return Ok(());
+                };
+                self.documents.insert(uri.clone(),text.to_string());
+                self.publish(&uri,out)?;
+            },
+            "textDocument/didSave"=>{
+                // A saved file can be included by any of the others.
+                let uris=self.documents.keys().cloned().collect::<Vec<_>>();
+                for uri in uris {
+                    self.publish(&uri,out)?;
+                }
+            },
+            "textDocument/didClose"=>{
+                self.documents.remove(&uri);
+                write_message(out,&json!({
+                    "jsonrpc":"2.0",
+                    "method":"textDocument/publishDiagnostics",
+                    "params":{"uri":uri,"diagnostics":[]},
+                }))?;
+            },
+            _=>{},
+        }
+        return Ok(());
+    }
+    fn publish(&self,uri:&str,out:&mut impl Write)->IoResult<()> {
+        let Some(text)=self.documents.get(uri) else {
+            return Ok(());
+        };
+        return write_message(out,&json!({
+            "jsonrpc":"2.0",
+            "method":"textDocument/publishDiagnostics",
+            "params":{"uri":uri,"diagnostics":diagnostics(uri,text)},
+        }));
+    }
+}
+
+
+/// Parses a document like a build does, and returns its errors and warnings. Its includes are read
+/// from disk.
+fn diagnostics(uri:&str,text:&str)->Vec<Value> {
+    let name=path_from_uri(uri).map(|p|p.display().to_string()).unwrap_or_else(||uri.to_string());
+    let sources=Sources::default();
+    let (file,source)=sources.add(name,text.to_string());
+    let (doc,errors,mut warnings,_)=DocumentParser::new(source,file,&sources).into_document();
+    let mut diagnostics=errors.iter()
+        .map(|e|lsp_diagnostic(&Diagnostic::error(e),file))
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        for (label,location) in Outline::new(&doc).undefined_footnotes() {
+            warnings.push(Warning{kind:WarningKind::UndefinedFootnote(label.to_string()),location:location.cloned()});
+        }
+    }
+    for warning in warnings.iter() {
+        diagnostics.push(lsp_diagnostic(&Diagnostic::warning(warning,Level::Warn,&sources),file));
+    }
+    return diagnostics;
+}
+/// A diagnostic for the document `file`. Ones in other files, like an include, are put at the
+/// start of it.
+fn lsp_diagnostic(diagnostic:&Diagnostic,file:&str)->Value {
+    let mut message=diagnostic.message.clone();
+    let range=match diagnostic.span {
+        Some((span_file,source,offset)) if span_file==file=>word_range(source,offset),
+        Some((span_file,_,_))=>{
+            message=format!("{} (in `{}`)",message,span_file);
+            range("",0,0)
+        },
+        None=>range("",0,0),
+    };
+    if let Some(note)=&diagnostic.note {
+        message=format!("{}\nnote: {}",message,note);
+    }
+    if let Some(help)=diagnostic.help {
+        message=format!("{}\nhelp: {}",message,help);
+    }
+    let related=diagnostic.labels.iter().map(|label|json!({
+        "location":{"uri":uri_from_path(Path::new(label.file)),"range":word_range(label.source,Some(label.offset))},
+        "message":label.message,
+    })).collect::<Vec<_>>();
+    let severity=match diagnostic.severity {
+        Severity::Error=>SEVERITY_ERROR,
+        Severity::Warning=>SEVERITY_WARNING,
+    };
+    let mut lsp=json!({
+        "range":range,
+        "severity":severity,
+        "source":env!("CARGO_PKG_NAME"),
+        "message":message,
+        "relatedInformation":related,
+    });
+    if let Some(code)=diagnostic.code {
+        lsp["code"]=json!(code);
+    }
+    return lsp;
+}
+
+
+/// The keys that can start a line in the block the cursor is in, after only letters on its line.
+fn completion(text:&str,at:usize)->Value {
+    let typed=text[..at].chars().rev().take_while(|c|is_key_char(*c)).map(char::len_utf8).sum::<usize>();
+    let start=at-typed;
+    let line_start=text[..start].rfind('\n').map_or(0,|i|i+1);
+    if !starts_statement(&text[line_start..start]) {
+        return json!([]);
+    }
+    let tree=SyntaxTree::new(text);
+    if in_comment(&tree,start) {
+        return json!([]);
+    }
+    let block=tree.path(start).into_iter().rev().find(|b|b.open<start);
+    let context=block.map(|b|b.kind);
+    // Most keys can only be given once.
+    let set=block.map(|b|b.keys()).unwrap_or_default().into_iter()
+        .filter(|(_,offset)|*offset!=start)
+        .map(|(key,_)|key)
+        .collect::<Vec<_>>();
+    let repeatable=|key:&str|ITEM_KINDS.contains(&key)||(context==Some(BlockKind::Metadata)&&(key=="style"||key=="data"));
+    let mut items=Vec::new();
+    for key in keys(context) {
+        if set.contains(&key)&&!repeatable(key) {
+            continue;
+        }
+        let kind=match ITEM_KINDS.contains(&key)||TOP_LEVEL_KEYS.contains(&key) {
+            true=>COMPLETION_KEYWORD,
+            false=>COMPLETION_PROPERTY,
+        };
+        let mut item=json!({"label":key,"kind":kind});
+        if let Some(doc)=key_doc(context,key) {
+            item["documentation"]=json!({"kind":"markdown","value":doc});
+        }
+        items.push(item);
+    }
+    if matches!(context,Some(BlockKind::Page|BlockKind::Vertical|BlockKind::Horizontal|BlockKind::Component|BlockKind::For)) {
+        for name in component_names(&tree) {
+            items.push(json!({"label":name,"kind":COMPLETION_FUNCTION,"detail":"component"}));
+        }
+    }
+    return Value::Array(items);
+}
+/// The docs for the key under the cursor, if it is one.
+fn hover(text:&str,at:usize)->Value {
+    let start=at-text[..at].chars().rev().take_while(|c|is_key_char(*c)).map(char::len_utf8).sum::<usize>();
+    let end=at+text[at..].chars().take_while(|c|is_key_char(*c)).map(char::len_utf8).sum::<usize>();
+    let word=&text[start..end];
+    let line_start=text[..start].rfind('\n').map_or(0,|i|i+1);
+    if word.is_empty()||!starts_statement(&text[line_start..start]) {
+        return Value::Null;
+    }
+    let tree=SyntaxTree::new(text);
+    if in_comment(&tree,start) {
+        return Value::Null;
+    }
+    let context=tree.path(start).into_iter().rev().find(|b|b.open<start).map(|b|b.kind);
+    if !keys(context).contains(&word) {
+        return Value::Null;
+    }
+    let Some(doc)=key_doc(context,word) else {
+        return Value::Null;
+    };
+    return json!({
+        "contents":{"kind":"markdown","value":format!("`{}`\n\n{}",word,doc)},
+        "range":range(text,start,end),
+    });
+}
+/// Every block that spans more than one line folds, leaving its `}` showing, and so does every
+/// comment that does.
+fn folding_ranges(text:&str)->Value {
+    let tree=SyntaxTree::new(text);
+    let mut ranges=Vec::new();
+    for block in tree.blocks() {
+        let start_line=line(text,block.start);
+        let end_line=match block.end {
+            Some(end)=>line(text,end).saturating_sub(1),
+            None=>line(text,text.len()),
+        };
+        if end_line>start_line {
+            ranges.push(json!({"startLine":start_line,"endLine":end_line}));
+        }
+    }
+    for (comment,start) in tree.comments() {
+        let (start_line,end_line)=(line(text,start),line(text,start+comment.len()));
+        if end_line>start_line {
+            ranges.push(json!({"startLine":start_line,"endLine":end_line,"kind":"comment"}));
+        }
+    }
+    return Value::Array(ranges);
+}
+/// An edit replacing the whole text with the formatted text. A document whose braces don't match
+/// isn't changed.
+fn formatting(text:&str)->Value {
+    match format(text) {
+        Some(formatted) if formatted!=text=>json!([{"range":range(text,0,text.len()),"newText":formatted}]),
+        Some(_)=>json!([]),
+        None=>Value::Null,
+    }
+}
+/// The pages, and the sections in each one.
+fn document_symbols(text:&str)->Value {
+    fn symbols(text:&str,nodes:&[Node],pages:&mut usize)->Vec<Value> {
+        let mut found=Vec::new();
+        for node in nodes {
+            let Node::Block(block)=node else {
+                continue;
+            };
+            let Body::Nodes(children)=&block.body else {
+                continue;
+            };
+            let (name,kind)=match block.kind {
+                BlockKind::Page=>{
+                    *pages+=1;
+                    (format!("page {}",pages),SYMBOL_MODULE)
+                },
+                BlockKind::Section=>(section_name(children),SYMBOL_OBJECT),
+                // Sections in other blocks belong to the page they are on.
+                _=>{
+                    found.extend(symbols(text,children,pages));
+                    continue;
+                },
+            };
+            let end=block.end.map_or(text.len(),|end|end+1);
+            found.push(json!({
+                "name":name,
+                "kind":kind,
+                "range":range(text,block.start,end),
+                "selectionRange":range(text,block.start,block.start+key(block.header).len()),
+                "children":symbols(text,children,pages),
+            }));
+        }
+        return found;
+    }
+    let tree=SyntaxTree::new(text);
+    return Value::Array(symbols(text,&tree.nodes,&mut 0));
+}
+/// A section is named after the first line of its content, without the `#`s of a heading.
+fn section_name(nodes:&[Node])->String {
+    let content=nodes.iter().find_map(|node|match node {
+        Node::Block(Block{body:Body::Content(lines),..})=>Some(lines),
+        _=>None,
+    });
+    let first=content.and_then(|lines|lines.iter().map(|l|l.trim_start_matches('#').trim()).find(|l|!l.is_empty()));
+    return first.unwrap_or("section").to_string();
+}
+fn component_names<'a>(tree:&SyntaxTree<'a>)->Vec<&'a str> {
+    return tree.nodes.iter().filter_map(|node|match node {
+        Node::Block(block) if block.kind==BlockKind::Component=>{
+            let name=key(block.header["component".len()..].trim_start());
+            (!name.is_empty()).then_some(name)
+        },
+        _=>None,
+    }).collect();
+}
+
+
+/// The keys of a kind of block, or of the top level for [`None`].
+fn keys(context:Option<BlockKind>)->Vec<&'static str> {
+    use BlockKind::*;
+    let lists:&[&[&'static str]]=match context {
+        None=>&[TOP_LEVEL_KEYS],
+        Some(Metadata)=>&[METADATA_KEYS],
+        Some(PageStyle)=>&[PAGE_STYLE_KEYS],
+        Some(NamedStyle|SectionStyle)=>&[SECTION_STYLE_KEYS],
+        Some(PageSize)=>&[PAGE_SIZE_KEYS],
+        Some(Markdown)=>&[MARKDOWN_KEYS],
+        Some(Section)=>&[SECTION_KEYS],
+        Some(Toc)=>&[TOC_KEYS],
+        Some(Page)=>&[&["style"],ITEM_KINDS],
+        Some(Vertical|Horizontal)=>&[ITEM_KEYS,ITEM_KINDS],
+        Some(Component|For)=>&[ITEM_KINDS],
+        Some(Vars|Content|Other)=>&[],
+    };
+    // Only named styles can extend another, and only other style blocks can use one.
+    return lists.iter()
+        .flat_map(|list|list.iter().copied())
+        .filter(|key|match context {
+            Some(NamedStyle)=>*key!="use",
+            Some(SectionStyle)=>*key!="extends",
+            _=>true,
+        })
+        .collect();
+}
+/// What a key does in a kind of block.
+fn key_doc(context:Option<BlockKind>,key:&str)->Option<&'static str> {
+    use BlockKind::*;
+    let doc=match (context,key) {
+        (Some(Metadata),"style")=>"`style { ... }` is the page style of every page. `style NAME { ... }` defines a named style, which section styles can `use`.",
+        (Some(Page),"style")=>"`style { ... }` is this page's style, which overrides the one in the metadata.",
+        (Some(PageSize),"width")=>"The width of the page, as a size.",
+        (Some(PageSize),"height")=>"The height of the page, as a size.",
+        (_,"style")=>"`style { ... }` is the section style, with keys like `width`, `font_size`, and `use`.",
+        (_,"metadata")=>"The title, page style, named styles, variables, and other settings of the document. It comes first.",
+        (_,"page")=>"A page of the document, with a `style` and the items on it.",
+        (_,"include")=>"`include \"FILE\"` puts the items or pages of another file here. The path is relative to this file.",
+        (_,"component")=>"`component NAME(PARAM, ...) { ... }` defines items that `NAME(\"ARG\", ...)` puts in place, with each `$PARAM` replaced by its argument.",
+        (_,"vertical")=>"`vertical { ... }` stacks its items from top to bottom.",
+        (_,"horizontal")=>"`horizontal { ... }` lays its items out from left to right.",
+        (_,"section")=>"`section { ... }` is markdown `content`, with an optional `style` and `tags`.",
+        (_,"toc")=>"`toc { ... }` lists the document's headings, with links to them.",
+        (_,"for")=>"`for NAME in DATA { ... }` repeats its items for each record of a `data` file, with `$NAME.FIELD` replaced. `where` and `sort` pick and order the records.",
+        (_,"title")=>"The title of the document: the rest of the line, with variables replaced.",
+        (_,"code_theme")=>"The theme code blocks are highlighted with, like `InspiredGitHub`.",
+        (_,"code_highlight")=>"`Inline` colors code with inline styles, and `Classes` with classes and a stylesheet.",
+        (_,"footnotes")=>"Where footnotes go: `Page` puts them at the end of each page, and `Document` at the end of the document.",
+        (_,"markdown")=>"`markdown { ... }` turns the markdown extensions `tables`, `tasklists`, `smart_punctuation`, and `heading_attributes` on or off.",
+        (_,"data")=>"`data NAME = \"FILE\"` reads a list of records from a `.json`, `.toml`, or `.csv` file, for `for` loops.",
+        (_,"vars")=>"`vars { NAME: VALUE }` defines variables, which are used as `$NAME`. A value is a color, a size, or text.",
+        (_,"use")=>"The named style this style starts from. The keys set here override it.",
+        (_,"extends")=>"The named style this one inherits from.",
+        (_,"width")=>"The width, as a size like `2in`, `12pt`, `96px`, `50mm`, or `30%`.",
+        (_,"height")=>"The height, as a size like `2in`, `12pt`, `96px`, `50mm`, or `30%`.",
+        (_,"vertical_text_align")=>"Where the text goes vertically: `Top`, `Center`, or `Bottom`.",
+        (_,"horizontal_text_align")=>"Where the text goes horizontally: `Left`, `Center`, or `Rigth`, which is how the parser spells it.",
+        (_,"align")=>"Which way the item floats: `Left`, `Right`, `Up`, or `Down`.",
+        (_,"font")=>"The font family: the rest of the line.",
+        (_,"font_size")=>"The font size, like `12pt`.",
+        (_,"text_color")=>"The text color, as `#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA`.",
+        (_,"background_color")=>"The background color, as `#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA`.",
+        (_,"margin")=>"The margin on every side. It can't be combined with `margin_left` and the others.",
+        (_,"margin_left")=>"The margin on the left side.",
+        (_,"margin_right")=>"The margin on the right side.",
+        (_,"margin_top")=>"The margin on the top side.",
+        (_,"margin_bottom")=>"The margin on the bottom side.",
+        (_,"page_size")=>"A paper size like `A4` or `Letter`, `PortraitLetter`, `LandscapeLetter`, `Webpage`, or a custom `page_size { ... }` with a `width` and `height`.",
+        (_,"orientation")=>"`Portrait` or `Landscape`, which turns the paper size.",
+        (_,"tables")=>"`true` or `false`: GitHub style tables.",
+        (_,"tasklists")=>"`true` or `false`: `- [ ]` and `- [x]` task list items.",
+        (_,"smart_punctuation")=>"`true` or `false`: curly quotes, dashes, and ellipses.",
+        (_,"heading_attributes")=>"`true` or `false`: `{#id .class}` after a heading.",
+        (_,"tags")=>"`tags: [NAME, ...]` marks the item for `--profile`. Items tagged only for other profiles are left out.",
+        (_,"content")=>"`content { ... }` is the section's markdown. Its lines end at the first one indented less than the first.",
+        (_,"content_file")=>"`content_file: \"FILE\"` reads the section's markdown from a file, relative to this one.",
+        (_,"depth")=>"The deepest heading level listed, from 1 to 6. Defaults to 3.",
+        (_,"pages")=>"The pages whose headings are listed, like `1, 3`. Defaults to every page.",
+        _=>return None,
+    };
+    return Some(doc);
+}
+fn is_key_char(c:char)->bool {
+    c.is_ascii_alphanumeric()||c=='_'
+}
+fn in_comment(tree:&SyntaxTree,offset:usize)->bool {
+    tree.comments().iter().any(|(comment,start)|*start<offset&&offset<=start+comment.len())
+}
+/// Whether what is before a word on its line means the word starts a line or block.
+fn starts_statement(before:&str)->bool {
+    let before=before.trim_end();
+    return before.is_empty()||before.ends_with('{')||before.ends_with('}');
+}
+
+
+/// The line `offset` is on, from 0.
+fn line(text:&str,offset:usize)->usize {
+    text[..offset].matches('\n').count()
+}
+/// Where `offset` is as an LSP position: its line, and how many UTF-16 code units into the line.
+fn position(text:&str,offset:usize)->Value {
+    let before=&text[..offset];
+    let line_start=before.rfind('\n').map_or(0,|i|i+1);
+    return json!({"line":line(text,offset),"character":before[line_start..].encode_utf16().count()});
+}
+fn range(text:&str,start:usize,end:usize)->Value {
+    json!({"start":position(text,start),"end":position(text,end)})
+}
+/// The range of the word at `offset`, like errors underline. [`None`] is the end of the text.
+fn word_range(text:&str,offset:Option<usize>)->Value {
+    let start=offset.unwrap_or_else(||text.trim_end().len()).min(text.len());
+    let line_end=text[start..].find('\n').map_or(text.len(),|i|start+i);
+    return range(text,start,start+word_len(&text[start..line_end]));
+}
+/// The offset of an LSP position. Positions past the end of a line are at its end.
+fn offset(text:&str,position:&Value)->usize {
+    let line=position["line"].as_u64().unwrap_or(0);
+    let character=position["character"].as_u64().unwrap_or(0) as usize;
+    let mut start=0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(i)=>start+=i+1,
+            None=>return text.len(),
+        }
+    }
+    let rest=&text[start..];
+    let rest=&rest[..rest.find('\n').unwrap_or(rest.len())];
+    let mut units=0;
+    for (i,c) in rest.char_indices() {
+        if units>=character {
+            return start+i;
+        }
+        units+=c.len_utf16();
+    }
+    return start+rest.len();
+}
+/// The path of a `file:` URI, with its escapes decoded.
+fn path_from_uri(uri:&str)->Option<PathBuf> {
+    return Some(PathBuf::from(percent_decode(uri.strip_prefix("file://")?)?));
+}
+fn uri_from_path(path:&Path)->String {
+    let path=path.canonicalize().unwrap_or_else(|_|path.to_path_buf());
+    let mut uri="file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric()||b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}",byte));
+        }
+    }
+    return uri;
+}
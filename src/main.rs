@@ -1,42 +1,656 @@
-use generic_parser::GenericParser;
 use std::{
     fs::{
-        read_to_string,
-        write as write_file,
+        File,
+        create_dir_all,
+    },
+    io::{
+        BufWriter,
+        Read,
+        Result as IoResult,
+        Write,
+        stdin,
+        stdout,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
     },
-    path::Path,
     env::args,
+    process::ExitCode,
+    thread::sleep,
+    time::Duration,
 };
 use parser::*;
 use render::*;
+use pdf::*;
+use sources::Sources;
+use watch::Watcher;
+use serve::ServeOptions;
+use diagnostic::{
+    Diagnostic,
+    ErrorFormat,
+    Report,
+};
+use warning::{
+    Level,
+    Levels,
+    Warning,
+    WarningKind,
+};
 
 
 mod parser;
 mod render;
+mod pdf;
+mod highlight;
+mod outline;
+mod sources;
+mod data;
+mod watch;
+mod serve;
+mod diagnostic;
+mod warning;
+mod syntax;
+mod format;
+mod lsp;
+mod url;
+
 
+const WATCH_INTERVAL:Duration=Duration::from_millis(300);
+const DEFAULT_PORT:u16=8000;
+/// The file name for stdin and stdout.
+const STDIO:&str="-";
+/// What errors call stdin.
+const STDIN_NAME:&str="<stdin>";
+/// A file failed to build.
+const EXIT_FAILURE:u8=1;
+/// The arguments were wrong.
+const EXIT_USAGE:u8=2;
 
-fn main() {
+
+fn main()->ExitCode {
     let mut args=args().collect::<Vec<_>>();
     let exe_name=args.remove(0);
     if args.contains(&"--help".to_string())||args.len()==0 {
         help(&exe_name);
-        return;
-    }
-    for file in args {
-        let path=Path::new(&file);
-        let name=path.file_stem().unwrap().to_str().unwrap();
-        let contents=read_to_string(&file).unwrap();
-        let doc_res=GenericParser::new(&contents,&file).into_document();
-        match doc_res {
-            Ok(doc)=>{
-                let html=doc.into_html(ParentDirection::None);
-                write_file(format!("{}.html",name),html).unwrap();
+        return ExitCode::SUCCESS;
+    }
+    if args[0]=="serve" {
+        args.remove(0);
+        return serve_command(args);
+    }
+    if args[0]=="fmt" {
+        args.remove(0);
+        return fmt_command(args);
+    }
+    if args[0]=="lsp" {
+        if args.len()>1 {
+            eprintln!("`lsp` takes no arguments");
+            return ExitCode::from(EXIT_USAGE);
+        }
+        return match lsp::run() {
+            Ok(true)=>ExitCode::SUCCESS,
+            Ok(false)=>ExitCode::from(EXIT_FAILURE),
+            Err(e)=>{
+                eprintln!("The language server stopped: {}",e);
+                ExitCode::from(EXIT_FAILURE)
             },
-            Err(e)=>e.print_with_context(&contents,false),
+        };
+    }
+    let (options,files,watch)=match parse_args(args) {
+        Ok(parsed)=>parsed,
+        Err(message)=>{
+            eprintln!("{}",message);
+            eprintln!("Run `{} --help` for usage",exe_name);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+    let mut failed=false;
+    let mut watchers=Vec::new();
+    for path in files {
+        let (built,inputs)=build(&path,&options);
+        failed|=!built;
+        watchers.push((path,Watcher::new(inputs)));
+    }
+    if !watch {
+        return if failed {ExitCode::from(EXIT_FAILURE)} else {ExitCode::SUCCESS};
+    }
+    if !options.report.quiet {
+        eprintln!("Watching for changes. Press Ctrl+C to stop");
+    }
+    loop {
+        sleep(WATCH_INTERVAL);
+        for (path,watcher) in watchers.iter_mut() {
+            if watcher.changed() {
+                // The inputs can change too, like when an include is added.
+                let (_,inputs)=build(path,&options);
+                *watcher=Watcher::new(inputs);
+            }
         }
     }
 }
+/// Returns the options, the input files, and whether to watch them.
+fn parse_args(args:Vec<String>)->Result<(Options,Vec<PathBuf>,bool),String> {
+    let mut formats=Vec::new();
+    let mut safe=false;
+    let mut profiles=Vec::new();
+    let mut watch=false;
+    let mut quiet=false;
+    let mut output=None;
+    let mut out_dir=None;
+    let mut error_format="human".to_string();
+    let mut color="auto".to_string();
+    let mut levels=Levels::default();
+    let mut files=Vec::new();
+    let mut args=split_values(args).into_iter();
+    while let Some(arg)=args.next() {
+        match arg.as_str() {
+            "--allow"|"--warn"|"--deny"=>set_levels(&mut levels,&arg,args.next())?,
+            "--deny-warnings"=>levels.deny_warnings(),
+            "--format"|"-f"=>{
+                let list=args.next().ok_or("Expected a format after `--format`")?;
+                for name in list.split(',') {
+                    let format=Format::from_name(name)
+                        .ok_or_else(||format!("Unknown format `{}`. Expected `html` or `pdf`",name))?;
+                    formats.push(format);
+                }
+            },
+            "--profile"=>{
+                let list=args.next().ok_or("Expected a profile after `--profile`")?;
+                profiles.extend(list.split(',').map(str::to_string));
+            },
+            "--output"|"-o"=>{
+                if output.is_some() {
+                    return Err("`--output` can only be given once".to_string());
+                }
+                output=Some(args.next().ok_or("Expected a file after `--output`")?);
+            },
+            "--out-dir"=>{
+                out_dir=Some(PathBuf::from(args.next().ok_or("Expected a folder after `--out-dir`")?));
+            },
+            "--error-format"=>error_format=args.next().ok_or("Expected `human` or `json` after `--error-format`")?,
+            "--color"=>color=args.next().ok_or("Expected `auto`, `always`, or `never` after `--color`")?,
+            "--watch"=>watch=true,
+            "--quiet"|"-q"=>quiet=true,
+            "--safe"=>safe=true,
+            "-"=>files.push(PathBuf::from(arg)),
+            _ if arg.starts_with('-')=>return Err(format!("Unknown option `{}`",arg)),
+            _=>files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() {
+        return Err("Expected a file to build".to_string());
+    }
+    if formats.is_empty() {
+        // `-o resume.pdf` is enough to pick the format.
+        let format=output.as_deref()
+            .and_then(|o|Path::new(o).extension())
+            .and_then(|e|e.to_str())
+            .and_then(Format::from_name);
+        formats.push(format.unwrap_or(Format::Html));
+    }
+    let stdin=files.iter().any(|f|f==Path::new(STDIO));
+    let outputs_per_file=formats.len()*profiles.len().max(1);
+    if output.is_some()||(stdin&&out_dir.is_none()) {
+        if files.len()>1||outputs_per_file>1 {
+            return Err("Only one file with one format and profile can be written to `--output` or stdout. Use `--out-dir` instead".to_string());
+        }
+    }
+    if output.is_some()&&out_dir.is_some() {
+        return Err("`--output` and `--out-dir` can't be used together".to_string());
+    }
+    if stdin&&watch {
+        return Err("Can't watch stdin".to_string());
+    }
+    let output=output.map(|o|if o==STDIO {Output::Stdout} else {Output::File(PathBuf::from(o))});
+    let format=error_format_from_names(&error_format,&color)?;
+    let report=Report{format,levels,quiet};
+    return Ok((Options{formats,safe,profiles,output,out_dir,report},files,watch));
+}
+/// Splits `--option=value` into `--option value`.
+fn split_values(args:Vec<String>)->Vec<String> {
+    let mut split=Vec::new();
+    for arg in args {
+        match arg.split_once('=') {
+            Some((option,value)) if option.starts_with("--")=>{
+                split.push(option.to_string());
+                split.push(value.to_string());
+            },
+            _=>split.push(arg),
+        }
+    }
+    return split;
+}
+fn error_format_from_names(format:&str,color:&str)->Result<ErrorFormat,String> {
+    return ErrorFormat::from_names(format,color).ok_or_else(||match format {
+        "human"|"json"=>format!("Unknown color `{}`. Expected `auto`, `always`, or `never`",color),
+        _=>format!("Unknown error format `{}`. Expected `human` or `json`",format),
+    });
+}
+/// Sets the level of each warning in `list`, the value of `--allow`, `--warn`, or `--deny`.
+fn set_levels(levels:&mut Levels,arg:&str,list:Option<String>)->Result<(),String> {
+    let level=match arg {
+        "--allow"=>Level::Allow,
+        "--warn"=>Level::Warn,
+        _=>Level::Deny,
+    };
+    let list=list.ok_or_else(||format!("Expected a warning after `{}`",arg))?;
+    for name in list.split(',') {
+        levels.set(name,level)?;
+    }
+    return Ok(());
+}
+fn serve_command(args:Vec<String>)->ExitCode {
+    let mut options=ServeOptions{port:DEFAULT_PORT,safe:false,profile:None,report:Report::default()};
+    let mut error_format="human".to_string();
+    let mut color="auto".to_string();
+    let mut file=None;
+    let mut args=split_values(args).into_iter();
+    while let Some(arg)=args.next() {
+        if arg=="--port" {
+            let Some(port)=args.next().and_then(|p|p.parse().ok()) else {
+                eprintln!("Expected a port number after `--port`");
+                return ExitCode::from(EXIT_USAGE);
+            };
+            options.port=port;
+        } else if arg=="--profile" {
+            let Some(profile)=args.next() else {
+                eprintln!("Expected a profile after `--profile`");
+                return ExitCode::from(EXIT_USAGE);
+            };
+            options.profile=Some(profile);
+        } else if arg=="--error-format"||arg=="--color" {
+            let Some(value)=args.next() else {
+                eprintln!("Expected a value after `{}`",arg);
+                return ExitCode::from(EXIT_USAGE);
+            };
+            if arg=="--color" {color=value} else {error_format=value}
+        } else if arg=="--allow"||arg=="--warn"||arg=="--deny" {
+            if let Err(message)=set_levels(&mut options.report.levels,&arg,args.next()) {
+                eprintln!("{}",message);
+                return ExitCode::from(EXIT_USAGE);
+            }
+        } else if arg=="--deny-warnings" {
+            options.report.levels.deny_warnings();
+        } else if arg=="-q"||arg=="--quiet" {
+            options.report.quiet=true;
+        } else if arg=="--safe" {
+            options.safe=true;
+        } else if file.is_none() {
+            file=Some(PathBuf::from(arg));
+        } else {
+            eprintln!("`serve` takes one file");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    }
+    let Some(file)=file else {
+        eprintln!("Expected a file to serve");
+        return ExitCode::from(EXIT_USAGE);
+    };
+    options.report.format=match error_format_from_names(&error_format,&color) {
+        Ok(format)=>format,
+        Err(message)=>{
+            eprintln!("{}",message);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+    if let Err(e)=serve::serve(file,options) {
+        eprintln!("Could not start the server: {}",e);
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    return ExitCode::SUCCESS;
+}
+fn fmt_command(args:Vec<String>)->ExitCode {
+    let mut report=Report::default();
+    let mut check=false;
+    let mut error_format="human".to_string();
+    let mut color="auto".to_string();
+    let mut files=Vec::new();
+    let mut args=split_values(args).into_iter();
+    while let Some(arg)=args.next() {
+        if arg=="--check" {
+            check=true;
+        } else if arg=="-q"||arg=="--quiet" {
+            report.quiet=true;
+        } else if arg=="--error-format"||arg=="--color" {
+            let Some(value)=args.next() else {
+                eprintln!("Expected a value after `{}`",arg);
+                return ExitCode::from(EXIT_USAGE);
+            };
+            if arg=="--color" {color=value} else {error_format=value}
+        } else if arg.starts_with('-')&&arg!=STDIO {
+            eprintln!("Unknown option `{}` for `fmt`",arg);
+            return ExitCode::from(EXIT_USAGE);
+        } else {
+            files.push(PathBuf::from(arg));
+        }
+    }
+    if files.is_empty() {
+        eprintln!("Expected a file to format");
+        return ExitCode::from(EXIT_USAGE);
+    }
+    report.format=match error_format_from_names(&error_format,&color) {
+        Ok(format)=>format,
+        Err(message)=>{
+            eprintln!("{}",message);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+    let mut failed=false;
+    for path in files {
+        failed|=!fmt_file(&path,check,&report);
+    }
+    return if failed {ExitCode::from(EXIT_FAILURE)} else {ExitCode::SUCCESS};
+}
+/// Formats a file in place, or stdin to stdout, after making sure the formatted source parses to
+/// the same document. With `check`, nothing is written, and it fails if the file isn't formatted.
+/// Returns whether it succeeded.
+fn fmt_file(path:&Path,check:bool,report:&Report)->bool {
+    let name=display_name(path);
+    let sources=Sources::default();
+    let (Ok((doc,_)),_)=parse(path,&sources,report) else {
+        eprintln!("Failed to format `{}`",name);
+        return false;
+    };
+    let file=match path==Path::new(STDIO) {
+        true=>STDIN_NAME.to_string(),
+        false=>path.to_string_lossy().into_owned(),
+    };
+    let source=sources.get(&file).unwrap_or_default();
+    let Some(formatted)=format::format(source) else {
+        eprintln!("Failed to format `{}`, because its braces don't match",name);
+        return false;
+    };
+    // The formatted source is parsed as the same file, so includes are found the same way.
+    let formatted_sources=Sources::default();
+    let (formatted_file,formatted_source)=formatted_sources.add(file,formatted);
+    let (formatted_doc,errors,..)=DocumentParser::new(formatted_source,formatted_file,&formatted_sources).into_document();
+    if !errors.is_empty()||formatted_doc!=doc {
+        eprintln!("Failed to format `{}`, because formatting it would change the document",name);
+        return false;
+    }
+    if check {
+        if source==formatted_source {
+            return true;
+        }
+        // When every line matches, one of them has more lines, or a line break is missing.
+        let (lines,formatted_lines)=(source.split('\n'),formatted_source.split('\n'));
+        let line=lines.clone().zip(formatted_lines.clone()).position(|(a,b)|a!=b)
+            .unwrap_or_else(||lines.count().min(formatted_lines.count())-1);
+        eprintln!("`{}` is not formatted, starting at line {}",name,line+1);
+        return false;
+    }
+    if path==Path::new(STDIO) {
+        return match write_output(formatted_source.as_bytes(),&Output::Stdout) {
+            Ok(())=>true,
+            Err(e)=>{
+                eprintln!("Could not write `{}`: {}",Output::Stdout,e);
+                false
+            },
+        };
+    }
+    if source==formatted_source {
+        return true;
+    }
+    if let Err(e)=write_output(formatted_source.as_bytes(),&Output::File(path.to_path_buf())) {
+        eprintln!("Could not write `{}`: {}",name,e);
+        return false;
+    }
+    if !report.quiet {
+        eprintln!("Formatted `{}`",name);
+    }
+    return true;
+}
+/// Parses and renders a file, then prints a summary of what was written. Returns whether it
+/// succeeded, and every file it used, which is what `--watch` watches.
+fn build(path:&Path,options:&Options)->(bool,Vec<PathBuf>) {
+    let sources=Sources::default();
+    let (doc,inputs)=parse(path,&sources,&options.report);
+    let Ok((doc,warnings))=doc else {
+        eprintln!("Failed to build `{}`",display_name(path));
+        return (false,inputs);
+    };
+    if options.report.warnings(&warnings,&sources) {
+        eprintln!("Failed to build `{}`",display_name(path));
+        return (false,inputs);
+    }
+    let mut written=Vec::new();
+    let mut failed=false;
+    let profiles=match options.profiles.is_empty() {
+        true=>vec![None],
+        false=>options.profiles.iter().map(|p|Some(p.as_str())).collect(),
+    };
+    for profile in profiles {
+        let (doc,warnings)=profile_document(&doc,path,profile);
+        // A profile with a denied warning isn't written.
+        if options.report.warnings(&warnings,&sources) {
+            failed=true;
+            continue;
+        }
+        for format in options.formats.iter() {
+            let output=options.output_for(path,profile,*format);
+            let mut renderer=format.renderer(options.safe);
+            let mut rendered=Vec::new();
+            let result=renderer.render(&doc,&mut rendered);
+            // Outputs with denied warnings aren't written.
+            if options.report.warnings(&renderer.take_warnings(),&sources) {
+                failed=true;
+                continue;
+            }
+            match result.and_then(|()|write_output(&rendered,&output)) {
+                Ok(())=>written.push(output.to_string()),
+                Err(e)=>{
+                    eprintln!("Could not write `{}`: {}",output,e);
+                    failed=true;
+                },
+            }
+        }
+    }
+    if failed {
+        eprintln!("Failed to build `{}`",display_name(path));
+    } else if !options.report.quiet {
+        eprintln!("Built `{}` -> {}",display_name(path),written.join(", "));
+    }
+    return (!failed,inputs);
+}
+fn write_output(rendered:&[u8],output:&Output)->IoResult<()> {
+    let mut out:BufWriter<Box<dyn Write>>=match output {
+        Output::Stdout=>BufWriter::new(Box::new(stdout().lock())),
+        Output::File(path)=>{
+            if let Some(dir)=path.parent().filter(|d|!d.as_os_str().is_empty()) {
+                create_dir_all(dir)?;
+            }
+            BufWriter::new(Box::new(File::create(path)?))
+        },
+    };
+    out.write_all(rendered)?;
+    return out.flush();
+}
+/// A parsed document with its warnings, or the errors as plain text.
+type Parsed=Result<(Document,Vec<Warning>),String>;
+/// Parses a file, or stdin for `-`, into `sources`, and prints any errors. Returns the document
+/// with its warnings, which are left for the caller to report, and every file it used, including
+/// images. When parsing fails, the errors are returned as plain text too.
+fn parse(path:&Path,sources:&Sources,report:&Report)->(Parsed,Vec<PathBuf>) {
+    let loaded=if path==Path::new(STDIO) {
+        let mut contents=String::new();
+        stdin().read_to_string(&mut contents).map(|_|sources.add(STDIN_NAME.to_string(),contents))
+    } else {
+        sources.load(path)
+    };
+    let (file,contents)=match loaded {
+        Ok(loaded)=>loaded,
+        Err(e)=>{
+            let message=format!("Could not read `{}`: {}",display_name(path),e);
+            eprintln!("{}",message);
+            return (Err(message),vec![path.to_path_buf()]);
+        },
+    };
+    let (doc,errors,warnings,truncated)=DocumentParser::new(contents,file,sources).into_document();
+    let mut inputs=sources.files().into_iter().map(PathBuf::from).collect::<Vec<_>>();
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            report.error(error);
+        }
+        if truncated&&report.format!=ErrorFormat::Json {
+            eprintln!("Stopped after {} errors",MAX_ERRORS);
+        }
+        let message=errors.iter()
+            .map(|e|diagnostic::render(&Diagnostic::error(e),false))
+            .collect::<Vec<_>>()
+            .join("\n");
+        return (Err(message),inputs);
+    }
+    let outline=outline::Outline::new(&doc);
+    // Images are relative to the document, like the output.
+    let dir=path.parent().unwrap_or(Path::new(""));
+    for image in outline.images.iter() {
+        if !image.contains(':') {
+            inputs.push(dir.join(image));
+        }
+    }
+    return (Ok((doc,warnings)),inputs);
+}
+/// The document without the items tagged only for other profiles, or the whole document without
+/// a profile. Also returns the warnings about what it is left with: nothing tagged for the
+/// profile, and footnotes whose definitions were left out.
+fn profile_document(doc:&Document,path:&Path,profile:Option<&str>)->(Document,Vec<Warning>) {
+    let mut warnings=Vec::new();
+    let doc=match profile {
+        Some(profile)=>{
+            if !doc.has_tag(profile) {
+                warnings.push(Warning {
+                    kind:WarningKind::EmptyProfile(profile.to_string(),display_name(path)),
+                    location:None,
+                });
+            }
+            doc.for_profile(profile)
+        },
+        None=>doc.clone(),
+    };
+    for (label,location) in outline::Outline::new(&doc).undefined_footnotes() {
+        warnings.push(Warning{kind:WarningKind::UndefinedFootnote(label.to_string()),location:location.cloned()});
+    }
+    return (doc,warnings);
+}
+fn display_name(path:&Path)->String {
+    if path==Path::new(STDIO) {
+        return STDIN_NAME.to_string();
+    }
+    return path.display().to_string();
+}
 fn help(exe_name:&str) {
     println!("Help:");
-    println!("    {} FILE1 FILE2 ...",exe_name);
+    println!("    {} [OPTIONS] FILE1 FILE2 ...",exe_name);
+    println!("    {} serve [--port PORT] [--profile NAME] [--safe] [--deny WARNINGS] [--deny-warnings] [-q] [--error-format FORMAT] [--color WHEN] FILE",exe_name);
+    println!("    {} fmt [--check] [-q] [--error-format FORMAT] [--color WHEN] FILE1 FILE2 ...",exe_name);
+    println!("    {} lsp",exe_name);
+    println!("    A FILE of `-` reads stdin, and writes to stdout unless `--out-dir` is given");
+    println!("Options:");
+    println!("    -f, --format FORMATS  Comma separated output formats. Defaults to `html`, or the");
+    println!("                          extension of `--output`");
+    println!("    -o, --output FILE     Where to write the only output. `-` is stdout");
+    println!("    --out-dir DIR         Where to write outputs. Defaults to the current folder");
+    println!("    --profile PROFILES    Comma separated profiles. Each one is written to `NAME-PROFILE.EXT`,");
+    println!("                          leaving out items that are only tagged for other profiles");
+    println!("    --safe                Strip raw HTML and scriptable links from the markdown content");
+    println!("    --watch               Rebuild whenever a file or anything it uses, like an include, changes");
+    println!("    -q, --quiet           Only print errors");
+    println!("    --deny-warnings       Fail the build on any warning, unless it is allowed");
+    println!("    --allow WARNINGS      Comma separated warnings to ignore. Also `--warn` and `--deny`, which");
+    println!("                          fails the build. Warnings: {}",warning::WARNING_NAMES.join(", "));
+    println!("    --error-format FORMAT How to print errors: `human`, or `json` for one object per line");
+    println!("    --color WHEN          Color errors: `auto`, `always`, or `never`. `auto` colors a terminal");
+    println!("                          unless NO_COLOR is set");
+    println!("Serve:");
+    println!("    Serves FILE as HTML at http://localhost:PORT/, which defaults to 8000. The page reloads");
+    println!("    after every rebuild and shows the error when a build fails");
+    println!("Fmt:");
+    println!("    Rewrites each FILE in the standard layout: 4 space indentation, `key: value`, style keys");
+    println!("    in a fixed order, and sizes and colors like `0.5in` and `#aabbcc`. `-` formats stdin to");
+    println!("    stdout. `--check` writes nothing, and fails when a file isn't formatted");
+    println!("Lsp:");
+    println!("    Runs a language server over stdin and stdout for editors, with errors and warnings as");
+    println!("    you type, completion and docs for keys, folding, an outline of the pages and sections,");
+    println!("    and formatting");
+    println!("Exit status:");
+    println!("    0 when every file was built, 1 when a file failed, and 2 for bad arguments");
+}
+
+
+struct Options {
+    formats:Vec<Format>,
+    safe:bool,
+    profiles:Vec<String>,
+    /// Where the only output goes, from `--output`.
+    output:Option<Output>,
+    out_dir:Option<PathBuf>,
+    report:Report,
+}
+impl Options {
+    /// Where to write `path` in `format`. Outputs are named after the input, with the profile
+    /// after a `-`, and go in the current folder unless `--out-dir` is given.
+    fn output_for(&self,path:&Path,profile:Option<&str>,format:Format)->Output {
+        if let Some(output)=&self.output {
+            return output.clone();
+        }
+        let stdin=path==Path::new(STDIO);
+        if stdin&&self.out_dir.is_none() {
+            return Output::Stdout;
+        }
+        let stem=match stdin {
+            true=>"stdin".to_string(),
+            false=>path.file_stem().map(|s|s.to_string_lossy().into_owned()).unwrap_or_else(||"document".to_string()),
+        };
+        let name=match profile {
+            Some(profile)=>format!("{}-{}.{}",stem,profile,format.extension()),
+            None=>format!("{}.{}",stem,format.extension()),
+        };
+        return Output::File(self.out_dir.as_deref().unwrap_or(Path::new("")).join(name));
+    }
+}
+#[derive(Clone)]
+enum Output {
+    Stdout,
+    File(PathBuf),
+}
+impl Display for Output {
+    fn fmt(&self,f:&mut Formatter)->FmtResult {
+        match self {
+            Output::Stdout=>write!(f,"stdout"),
+            Output::File(path)=>write!(f,"{}",path.display()),
+        }
+    }
+}
+#[derive(Copy,Clone)]
+enum Format {
+    Html,
+    Pdf,
+}
+impl Format {
+    fn from_name(name:&str)->Option<Self> {
+        match name {
+            "html"=>Some(Format::Html),
+            "pdf"=>Some(Format::Pdf),
+            _=>None,
+        }
+    }
+    fn extension(&self)->&'static str {
+        match self {
+            Format::Html=>"html",
+            Format::Pdf=>"pdf",
+        }
+    }
+    fn renderer(&self,safe:bool)->Box<dyn Renderer> {
+        match self {
+            Format::Html if safe=>Box::new(HtmlRenderer::safe()),
+            Format::Html=>Box::new(HtmlRenderer::default()),
+            Format::Pdf=>Box::new(PdfRenderer::default()),
+        }
+    }
 }
@@ -0,0 +1,255 @@
+use pulldown_cmark::{
+    Parser,
+    Event,
+    Tag,
+    HeadingLevel,
+};
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use crate::parser::{
+    Document,
+    Item,
+    Section,
+    Toc,
+    MarkdownOptions,
+};
+use crate::warning::Location;
+
+
+const DEFAULT_TOC_DEPTH:u8=3;
+
+
+/// Every heading and footnote in a document, in the order the renderers visit them.
+///
+/// The ids are worked out up front so a `toc` can link to headings on later pages, and so every
+/// backend gives a heading the same id.
+#[derive(Default)]
+pub struct Outline {
+    pub headings:Vec<Heading>,
+    pub footnote_definitions:HashSet<String>,
+    /// Every footnote reference's label and page number, in order.
+    pub footnote_references:Vec<(String,usize)>,
+    /// Where the section that first references each footnote is, for warnings.
+    pub footnote_locations:HashMap<String,Location>,
+    /// Every image's destination, in order.
+    pub images:Vec<String>,
+}
+pub struct Heading {
+    pub level:u8,
+    pub text:String,
+    pub id:String,
+    /// The page number, starting from 1.
+    pub page:usize,
+}
+impl Outline {
+    pub fn new(document:&Document)->Self {
+        let markdown=document.metadata.markdown.clone().unwrap_or_default();
+        let mut collector=Collector {
+            markdown,
+            used_ids:HashSet::new(),
+            outline:Outline::default(),
+            page:0,
+        };
+        for (i,page) in document.pages.iter().enumerate() {
+            collector.page=i+1;
+            for item in page.items.iter() {
+                collector.item(item);
+            }
+        }
+        return collector.outline;
+    }
+    /// Labels that are referenced but never defined, without duplicates, and where they are
+    /// first referenced.
+    pub fn undefined_footnotes(&self)->Vec<(&str,Option<&Location>)> {
+        let mut undefined:Vec<(&str,Option<&Location>)>=Vec::new();
+        for (label,_) in self.footnote_references.iter() {
+            if !self.footnote_definitions.contains(label)&&!undefined.iter().any(|(l,_)|l==label) {
+                undefined.push((label.as_str(),self.footnote_locations.get(label)));
+            }
+        }
+        return undefined;
+    }
+    pub fn page_has_footnotes(&self,page:usize)->bool {
+        self.footnote_references.iter().any(|(_,p)|*p==page)
+    }
+    /// Builds the markdown for a table of contents as a nested list of links.
+    pub fn toc_markdown(&self,toc:&Toc)->Vec<String> {
+        let depth=toc.depth.unwrap_or(DEFAULT_TOC_DEPTH);
+        let headings=self.headings.iter()
+            .filter(|h|h.level<=depth)
+            .filter(|h|toc.pages.as_ref().map(|pages|pages.contains(&h.page)).unwrap_or(true))
+            .collect::<Vec<_>>();
+        let top=headings.iter().map(|h|h.level).min().unwrap_or(1);
+        let mut lines=Vec::new();
+        let mut previous=top;
+        for heading in headings {
+            // Markdown can't skip a nesting level, so a deeper heading only goes one level down.
+            let level=heading.level.min(previous+1);
+            let indent="  ".repeat((level-top) as usize);
+            lines.push(format!("{}- [{}](#{})",indent,escape_markdown(&heading.text),heading.id));
+            previous=level;
+        }
+        return lines;
+    }
+}
+/// Turns heading text into an id like GitHub does: lowercase letters, numbers, `-`, and `_`, with
+/// spaces replaced by `-`.
+pub fn slug(text:&str)->String {
+    let mut out=String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric()||c=='_'||c=='-' {
+            out.extend(c.to_lowercase());
+        } else if c==' ' {
+            out.push('-');
+        }
+    }
+    if out.is_empty() {
+        out.push_str("section");
+    }
+    return out;
+}
+fn escape_markdown(text:&str)->String {
+    let mut out=String::new();
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    return out;
+}
+
+/// Numbers footnotes in the order they are first referenced and holds their rendered contents
+/// until they are placed. Labels are shared by the whole document.
+#[derive(Default)]
+pub struct Footnotes {
+    numbers:HashMap<String,usize>,
+    reference_counts:HashMap<String,usize>,
+    definitions:HashMap<String,String>,
+    placed:HashSet<String>,
+}
+pub struct Footnote {
+    pub number:usize,
+    pub content:String,
+    /// How many times it was referenced. Each reference gets its own back link target.
+    pub references:usize,
+}
+impl Footnotes {
+    fn number(&mut self,label:&str)->usize {
+        let next=self.numbers.len()+1;
+        return *self.numbers.entry(label.to_string()).or_insert(next);
+    }
+    /// Returns the footnote's number, and which reference to it this is, starting from 1.
+    pub fn reference(&mut self,label:&str)->(usize,usize) {
+        let number=self.number(label);
+        let count=self.reference_counts.entry(label.to_string()).or_insert(0);
+        *count+=1;
+        return (number,*count);
+    }
+    pub fn define(&mut self,label:&str,content:String) {
+        self.definitions.insert(label.to_string(),content);
+    }
+    /// Takes the footnotes that have been defined but not placed yet, in number order.
+    pub fn take(&mut self)->Vec<Footnote> {
+        let mut labels=self.definitions.keys()
+            .filter(|label|!self.placed.contains(*label))
+            .cloned()
+            .collect::<Vec<_>>();
+        // Footnotes that aren't referenced yet have no number, so they go last, sorted by label.
+        labels.sort_by(|a,b|{
+            let a_number=self.numbers.get(a).copied().unwrap_or(usize::MAX);
+            let b_number=self.numbers.get(b).copied().unwrap_or(usize::MAX);
+            a_number.cmp(&b_number).then_with(||a.cmp(b))
+        });
+        let mut footnotes=Vec::new();
+        for label in labels {
+            let number=self.number(&label);
+            let content=self.definitions.get(&label).cloned().unwrap_or_default();
+            let references=self.reference_counts.get(&label).copied().unwrap_or(0);
+            self.placed.insert(label);
+            footnotes.push(Footnote{number,content,references});
+        }
+        return footnotes;
+    }
+}
+
+
+struct Collector {
+    markdown:MarkdownOptions,
+    used_ids:HashSet<String>,
+    outline:Outline,
+    page:usize,
+}
+impl Collector {
+    fn item(&mut self,item:&Item) {
+        match item {
+            Item::Vertical{items,..}|Item::Horizontal{items,..}=>{
+                for item in items.iter() {
+                    self.item(item);
+                }
+            },
+            Item::Section(section)=>self.section(section),
+            Item::Toc(_)=>{},
+        }
+    }
+    fn section(&mut self,section:&Section) {
+        let source=section.content.join("\n");
+        let options=self.markdown.merge(section.style.as_ref().and_then(|s|s.markdown.as_ref())).options();
+        let mut current:Option<(u8,Option<String>,String)>=None;
+        for event in Parser::new_ext(&source,options) {
+            match event {
+                Event::Start(Tag::Heading(level,id,_))=>{
+                    current=Some((level_number(level),id.map(str::to_string),String::new()));
+                },
+                Event::Text(text)|Event::Code(text)=>{
+                    if let Some((_,_,heading_text))=&mut current {
+                        heading_text.push_str(&text);
+                    }
+                },
+                Event::End(Tag::Heading(..))=>{
+                    if let Some((level,id,text))=current.take() {
+                        let id=self.unique_id(id.unwrap_or_else(||slug(&text)));
+                        self.outline.headings.push(Heading{level,text,id,page:self.page});
+                    }
+                },
+                Event::Start(Tag::FootnoteDefinition(label))=>{
+                    self.outline.footnote_definitions.insert(label.to_string());
+                },
+                Event::Start(Tag::Image(_,dest,_))=>{
+                    self.outline.images.push(dest.to_string());
+                },
+                Event::FootnoteReference(label)=>{
+                    if let Some(location)=&section.location {
+                        self.outline.footnote_locations.entry(label.to_string()).or_insert_with(||location.clone());
+                    }
+                    self.outline.footnote_references.push((label.to_string(),self.page));
+                },
+                _=>{},
+            }
+        }
+    }
+    /// Adds `-1`, `-2`, etc. to ids that are already taken.
+    fn unique_id(&mut self,id:String)->String {
+        let mut unique=id.clone();
+        let mut n=1;
+        while self.used_ids.contains(&unique) {
+            unique=format!("{}-{}",id,n);
+            n+=1;
+        }
+        self.used_ids.insert(unique.clone());
+        return unique;
+    }
+}
+fn level_number(level:HeadingLevel)->u8 {
+    use HeadingLevel::*;
+    match level {
+        H1=>1,
+        H2=>2,
+        H3=>3,
+        H4=>4,
+        H5=>5,
+        H6=>6,
+    }
+}
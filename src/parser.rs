@@ -3,40 +3,765 @@ use generic_parser::{
     Error,
     EOFError,
 };
+use crate::highlight::THEMES;
+use crate::sources::Sources;
+use crate::data;
+use crate::warning::{
+    Location,
+    Warning,
+    WarningKind,
+};
+use crate::diagnostic::did_you_mean;
+use crate::syntax::SyntaxTree;
+use serde_json::Value;
+use pulldown_cmark::{
+    Parser as MarkdownParser,
+    BrokenLink,
+    LinkType,
+    Options,
+};
 use std::{
     fmt::{
         Display,
         Formatter,
         Result as FmtResult,
     },
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    fs::canonicalize,
 };
 
 
+/// A [`GenericParser`] plus what the document has defined so far, like its named styles.
+pub struct DocumentParser<'doc> {
+    parser:GenericParser<'doc,ParseError<'doc>>,
+    sources:&'doc Sources,
+    source:&'doc str,
+    file:&'doc str,
+    /// This file and the files that included it, to catch include cycles.
+    includes:Vec<PathBuf>,
+    state:State<'doc>,
+}
+/// What the whole document shares, which is passed along to the parsers for included files and
+/// expanded components.
+#[derive(Default)]
+struct State<'doc> {
+    /// The named styles from the metadata, in the order they are defined.
+    named_styles:Vec<NamedStyle<'doc>>,
+    /// The named styles with everything they extend filled in.
+    styles:HashMap<String,SectionStyle>,
+    vars:HashMap<String,Var>,
+    /// The metadata's markdown options, which each section's own options are merged into.
+    markdown:MarkdownOptions,
+    components:HashMap<String,Component<'doc>>,
+    /// The records read from each `data` file.
+    data:HashMap<String,Vec<Value>>,
+    /// The components being expanded, to catch components that use themselves.
+    expanding:Vec<String>,
+    /// The errors so far, up to [`MAX_ERRORS`].
+    errors:Vec<SourceError<'doc>>,
+    /// Whether there were more errors than were kept, or parsing stopped before the end of a file
+    /// because of them.
+    truncated:bool,
+    /// Every named style and where it is defined, and the ones that are used, to warn about the
+    /// rest.
+    defined_styles:Vec<(String,Location)>,
+    used_styles:HashSet<String>,
+}
+/// A parse error, and the file it is in.
+pub struct SourceError<'doc> {
+    pub kind:ErrorKind,
+    /// Where in `source` the error is, or [`None`] at the end of it.
+    pub offset:Option<usize>,
+    pub labels:Vec<Label<'doc>>,
+    pub help:Option<String>,
+    pub file:&'doc str,
+    /// The contents of `file`.
+    pub source:&'doc str,
+}
+/// What an error is about and where, plus any notes on it.
+#[derive(Debug)]
+pub struct ParseError<'doc> {
+    pub kind:ErrorKind,
+    pub offset:Option<usize>,
+    /// Boxed, since most errors have none and errors are passed around a lot.
+    pub notes:Option<Box<Notes<'doc>>>,
+}
+impl<'doc> ParseError<'doc> {
+    fn new(kind:ErrorKind,offset:Option<usize>,labels:Vec<Label<'doc>>,help:Option<String>)->Self {
+        let notes=(!labels.is_empty()||help.is_some()).then(||Box::new(Notes{labels,help}));
+        ParseError{kind,offset,notes}
+    }
+}
+/// Other places an error points at, like where something was first defined, and a suggestion for
+/// fixing it, like the name that was probably meant.
+#[derive(Debug)]
+pub struct Notes<'doc> {
+    pub labels:Vec<Label<'doc>>,
+    pub help:Option<String>,
+}
+/// A note on another part of the source.
+#[derive(Debug)]
+pub struct Label<'doc> {
+    pub file:&'doc str,
+    pub source:&'doc str,
+    pub offset:usize,
+    pub message:String,
+}
+impl<'doc> Label<'doc> {
+    /// A label on `part`, which must be a slice of `source` for there to be one.
+    fn at(file:&'doc str,source:&'doc str,part:&str,message:&str)->Option<Self> {
+        let offset=offset_in(source,part)?;
+        return Some(Label{file,source,offset,message:message.to_string()});
+    }
+}
+struct Component<'doc> {
+    name:&'doc str,
+    /// Whether the component has been used, to warn about it if it never is.
+    used:bool,
+    params:Vec<&'doc str>,
+    /// The source between the component's braces.
+    body:&'doc str,
+    /// The file the component is defined in, and that file's contents.
+    file:&'doc str,
+    source:&'doc str,
+}
+struct NamedStyle<'doc> {
+    name:&'doc str,
+    style:SectionStyle,
+    extends:Option<Extends<'doc>>,
+}
+/// Where each key of a block was first defined, by the name [`ErrorKind::AlreadyDefined`] uses.
+type Defined=HashMap<&'static str,usize>;
+/// The name of the style a style builds on, and the error to report if it never gets defined.
+type Extends<'doc>=(&'doc str,Error<'doc,ParseError<'doc>>);
+impl<'doc> DocumentParser<'doc> {
+    /// `source` and `file` should come from `sources`, which included files are read into.
+    pub fn new(source:&'doc str,file:&'doc str,sources:&'doc Sources)->Self {
+        DocumentParser {
+            parser:GenericParser::new(source,file),
+            sources,
+            source,
+            file,
+            includes:vec![canonicalize(file).unwrap_or_else(|_|PathBuf::from(file))],
+            state:State::default(),
+        }
+    }
+    /// Subparsers only look ahead, so they don't get the document's definitions.
+    fn subparser(&mut self)->DocumentParser<'doc> {
+        DocumentParser {
+            parser:self.parser.subparser(),
+            sources:self.sources,
+            source:self.source,
+            file:self.file,
+            includes:Vec::new(),
+            state:State::default(),
+        }
+    }
+    fn finish(self) {
+        self.parser.finish();
+    }
+    /// Resolves a path from the document against the directory of the file it is in.
+    fn relative_path(&self,path:&str)->PathBuf {
+        let dir=Path::new(self.file).parent().unwrap_or(Path::new(""));
+        return dir.join(path);
+    }
+    fn finish_error(self) {
+        self.parser.finish_error();
+    }
+    /// Parses `include "FILE"` and then everything in that file with `parse`.
+    fn include<T>(&mut self,parse:impl FnMut(&mut DocumentParser<'doc>)->Result<'doc,Vec<T>>)->Result<'doc,Vec<T>> {
+        if !self.then("include")? {
+            return Err(self.create_error(ErrorKind::ExpectedInclude,false));
+        }
+        self.skip_spaces();
+        let mut sp=self.subparser();
+        let relative=sp.string()?;
+        sp.finish_error();
+        let path=self.relative_path(&relative);
+        let canonical=canonicalize(&path).unwrap_or_else(|_|path.clone());
+        if let Some(i)=self.includes.iter().position(|p|*p==canonical) {
+            let chain=self.includes[i..].iter()
+                .chain(Some(&canonical))
+                .map(|p|p.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(self.create_error(ErrorKind::IncludeCycle(chain.join(" -> ")),true));
+        }
+        let (file,contents)=match self.sources.load(&path) {
+            Ok(loaded)=>loaded,
+            Err(e)=>return Err(self.create_error(ErrorKind::ReadFile(path.display().to_string(),e.to_string()),true)),
+        };
+        self.string()?;
+        let mut includes=self.includes.clone();
+        includes.push(canonical);
+        return Ok(self.parse_nested(contents,file,includes,parse));
+    }
+    /// Parses all of `source` with `parse`. It shares the document's styles, variables, and
+    /// components, and components it defines are kept.
+    fn parse_nested<T>(&mut self,source:&'doc str,file:&'doc str,includes:Vec<PathBuf>,mut parse:impl FnMut(&mut DocumentParser<'doc>)->Result<'doc,Vec<T>>)->Vec<T> {
+        let mut nested=DocumentParser {
+            parser:GenericParser::new(source,file),
+            sources:self.sources,
+            source,
+            file,
+            includes,
+            state:std::mem::take(&mut self.state),
+        };
+        let mut parsed=Vec::new();
+        while !nested.skip_whitespace().is_eof()&&!nested.too_many_errors() {
+            if let Some(values)=nested.recover(|parser|parse(parser)) {
+                parsed.extend(values);
+            }
+        }
+        nested.state.truncated|=!nested.is_eof();
+        self.state=nested.state;
+        return parsed;
+    }
+    /// Parses with `parse`, which gets a subparser with this parser's state. If that fails, the
+    /// error is kept and everything `parse` was given is skipped, up to the end of the line or of
+    /// the block that starts on it, so the rest of the document still gets checked.
+    fn recover<T>(&mut self,parse:impl FnOnce(&mut DocumentParser<'doc>)->Result<'doc,T>)->Option<T> {
+        let mut sp=self.subparser();
+        sp.includes=std::mem::take(&mut self.includes);
+        sp.state=std::mem::take(&mut self.state);
+        let result=parse(&mut sp);
+        self.includes=std::mem::take(&mut sp.includes);
+        self.state=std::mem::take(&mut sp.state);
+        match result {
+            Ok(value)=>{
+                sp.finish();
+                return Some(value);
+            },
+            Err(error)=>{
+                sp.finish_error();
+                self.record(error);
+                self.skip_failed();
+                return None;
+            },
+        }
+    }
+    /// Keeps an error from this parser's file, unless there are too many already.
+    fn record(&mut self,error:Error<'doc,ParseError<'doc>>) {
+        // Once the file has ended too early, anything after that only follows from it.
+        let ended=self.state.errors.iter()
+            .any(|e|std::ptr::eq(e.source,self.source)&&matches!(e.kind,ErrorKind::UnexpectedEof|ErrorKind::UnterminatedComment));
+        if ended {
+            return;
+        }
+        if self.too_many_errors() {
+            self.state.truncated=true;
+            return;
+        }
+        let ParseError{kind,offset,notes}=error.kind;
+        let Notes{labels,help}=notes.map(|notes|*notes).unwrap_or(Notes{labels:Vec::new(),help:None});
+        self.state.errors.push(SourceError{kind,offset,labels,help,file:self.file,source:self.source});
+    }
+    fn too_many_errors(&self)->bool {
+        self.state.errors.len()>=MAX_ERRORS
+    }
+    /// Skips past something that failed to parse: to the end of its line, or of the block that
+    /// starts on it. A `}` that ends the block around it is left alone.
+    fn skip_failed(&mut self) {
+        let start=self.offset();
+        while !self.is_eof() {
+            self.until_any(&["{","}","\"","//","/*","\n","\r\n"]);
+            if self.is_eof() {
+                break;
+            } else if self.skip_comment() {
+                continue;
+            } else if self.then("{").unwrap_or(false) {
+                if self.block_body(ErrorKind::UnexpectedEof).is_err() {
+                    self.until_any(&[]);
+                }
+                break;
+            } else if self.test("\"").unwrap_or(false) {
+                // An unterminated string stops at the end of the line, which ends the skip.
+                let _=self.string();
+            } else {
+                if !self.test("}").unwrap_or(false) {
+                    let _=self.then_any(NEWLINE);
+                }
+                break;
+            }
+        }
+        // A stray `}` would stop everything after it from being parsed.
+        if self.offset()==start {
+            let _=self.then("}");
+        }
+    }
+    /// Skips spaces, line breaks, and comments.
+    fn skip_whitespace(&mut self)->&mut Self {
+        loop {
+            self.skip(EXT_WHITESPACE);
+            if !self.skip_comment() {
+                return self;
+            }
+        }
+    }
+    /// Skips spaces and comments, but not line breaks, so what comes next is on the same line
+    /// unless a `/* */` comment spans lines.
+    fn skip_spaces(&mut self)->&mut Self {
+        loop {
+            self.skip(WHITESPACE);
+            if !self.skip_comment() {
+                return self;
+            }
+        }
+    }
+    /// Skips a `//` comment up to the end of its line, or a `/* */` comment, if one is next.
+    /// Returns whether there was one.
+    fn skip_comment(&mut self)->bool {
+        if self.then("//").unwrap_or(false) {
+            self.until_any(NEWLINE);
+            return true;
+        }
+        let offset=self.offset();
+        if !self.then("/*").unwrap_or(false) {
+            return false;
+        }
+        self.until_any(&["*/"]);
+        if !self.then("*/").unwrap_or(false) {
+            let error=ParseError::new(ErrorKind::UnterminatedComment,Some(offset),Vec::new(),None);
+            let error=self.create_diagnostic(error,true);
+            self.record(error);
+        }
+        return true;
+    }
+    /// How far into the source this parser is, in bytes.
+    fn offset(&mut self)->usize {
+        let rest=self.while_any(&[]);
+        return rest.as_ptr() as usize-self.source.as_ptr() as usize;
+    }
+    /// An error at the current position. Important errors are reported even when the parser was
+    /// only trying something.
+    fn create_error(&mut self,kind:ErrorKind,important:bool)->Error<'doc,ParseError<'doc>> {
+        return self.create_diagnostic(ParseError::new(kind,None,Vec::new(),None),important);
+    }
+    /// Like [`create_error`](Self::create_error), for errors with labels or help.
+    fn create_diagnostic(&mut self,mut error:ParseError<'doc>,important:bool)->Error<'doc,ParseError<'doc>> {
+        if error.offset.is_none() {
+            error.offset=Some(self.offset());
+        }
+        return self.parser.create_error(error,important);
+    }
+    /// Errors if `value` is already set, pointing back at where `what` was first defined.
+    /// Otherwise remembers that `what` is defined here.
+    fn define<T>(&mut self,defined:&mut Defined,what:&'static str,value:&Option<T>)->Result<'doc,()> {
+        if value.is_some() {
+            return Err(self.already_defined(defined,what));
+        }
+        self.mark_defined(defined,what);
+        return Ok(());
+    }
+    fn mark_defined(&mut self,defined:&mut Defined,what:&'static str) {
+        let offset=self.offset();
+        defined.entry(what).or_insert(offset);
+    }
+    fn already_defined(&mut self,defined:&Defined,what:&'static str)->Error<'doc,ParseError<'doc>> {
+        let labels=defined.get(what).map(|&offset|self.label(offset,"first defined here")).into_iter().collect();
+        return self.create_diagnostic(ParseError::new(ErrorKind::AlreadyDefined(what),None,labels,None),true);
+    }
+    fn location(&mut self)->Location {
+        Location{file:self.file.to_string(),offset:self.offset()}
+    }
+    /// A label pointing at `offset` in this parser's file.
+    fn label(&self,offset:usize,message:&str)->Label<'doc> {
+        Label{file:self.file,source:self.source,offset,message:message.to_string()}
+    }
+    /// An error for a key `block` doesn't have, at the key, suggesting the closest one it does.
+    fn unknown_key(&mut self,block:&'static str,key:&str,keys:&[&str])->Error<'doc,ParseError<'doc>> {
+        let help=did_you_mean(key,keys.iter().copied());
+        let offset=offset_in(self.source,key);
+        return self.create_diagnostic(ParseError::new(ErrorKind::UnknownKey(block,key.to_string()),offset,Vec::new(),help),true);
+    }
+    /// An error at `at`, a name that was just parsed, with a suggestion.
+    fn create_error_with_help(&mut self,kind:ErrorKind,at:&str,help:Option<String>)->Error<'doc,ParseError<'doc>> {
+        let offset=offset_in(self.source,at);
+        return self.create_diagnostic(ParseError::new(kind,offset,Vec::new(),help),true);
+    }
+    /// Expands `NAME("ARG", ...)` into the component's items, with each `$PARAM` in its body
+    /// replaced by the argument.
+    fn expand_component(&mut self)->Result<'doc,Vec<Item>> {
+        let call=self.offset();
+        let name=self.while_any(VAR_NAME_CHARS);
+        let Some(component)=self.state.components.get_mut(name) else {
+            let help=did_you_mean(name,self.state.components.keys().map(String::as_str));
+            return Err(self.create_error_with_help(ErrorKind::UndefinedComponent(name.to_string()),name,help));
+        };
+        component.used=true;
+        let (params,body,file,source)=(component.params.clone(),component.body,component.file,component.source);
+        if !self.then("(")? {
+            return Err(self.create_error(ErrorKind::ExpectedArguments,true));
+        }
+        let mut args=Vec::new();
+        while !self.skip_whitespace().then(")")? {
+            if args.len()>0&&!self.then(",")? {
+                return Err(self.create_error(ErrorKind::ExpectedArguments,true));
+            }
+            self.skip_whitespace();
+            args.push(self.string()?);
+        }
+        if args.len()!=params.len() {
+            return Err(self.create_error(ErrorKind::WrongArgumentCount(name.to_string(),params.len(),args.len()),true));
+        }
+        if self.state.expanding.iter().any(|n|n==name) {
+            let mut chain=self.state.expanding.clone();
+            chain.push(name.to_string());
+            return Err(self.create_error(ErrorKind::RecursiveComponent(chain.join(" -> ")),true));
+        }
+        let expanded=substitute(body,false,|param|{
+            params.iter().position(|p|*p==param).map(|i|args[i].clone())
+        });
+        self.state.expanding.push(name.to_string());
+        let items=self.expand(call,body,file,source,expanded);
+        self.state.expanding.pop();
+        return items;
+    }
+    /// Parses the items in `expanded`, which was made from `body`, a slice of `source`, for the
+    /// component use or `for` loop at `call`. Errors in it get a label there.
+    fn expand(&mut self,call:usize,body:&str,file:&'doc str,source:&str,expanded:String)->Result<'doc,Vec<Item>> {
+        // Pad the expansion so errors in it point at the body's lines in the original file.
+        let offset=body.as_ptr() as usize-source.as_ptr() as usize;
+        let before=&source[..offset];
+        let line_start=before.rfind('\n').map(|i|i+1).unwrap_or(0);
+        let mut padded="\n".repeat(before.matches('\n').count());
+        padded.push_str(&" ".repeat(before[line_start..].chars().count()));
+        padded.push_str(&expanded);
+        let (_,padded)=self.sources.add(file.to_string(),padded);
+        let errors=self.state.errors.len();
+        let items=self.parse_nested(padded,file,self.includes.clone(),|parser|parser.items());
+        for error in self.state.errors[errors..].iter_mut() {
+            error.labels.push(Label{file:self.file,source:self.source,offset:call,message:"expanded from here".to_string()});
+        }
+        return Ok(items);
+    }
+    /// Reads up to the `}` that closes a block whose `{` was just parsed, without parsing what is
+    /// in it. Strings, comments, and content blocks are skipped over, since they can hold braces.
+    fn block_body(&mut self,end_error:ErrorKind)->Result<'doc,&'doc str> {
+        let start=self.while_any(&[]);
+        let mut depth=0;
+        let end=loop {
+            let chunk=self.until_any(&["{","}","\"","//","/*","content"]);
+            if self.is_eof() {
+                return Err(self.create_error(end_error,true));
+            }
+            if self.skip_comment() {
+                continue;
+            } else if self.then("content")? {
+                self.skip_spaces();
+                if self.test("{")? {
+                    self.content_block()?;
+                }
+            } else if self.then("{")? {
+                depth+=1;
+            } else if self.test("}")? {
+                if depth==0 {
+                    break chunk;
+                }
+                self.then("}")?;
+                depth-=1;
+            } else {
+                self.string()?;
+            }
+        };
+        self.then("}")?;
+        let start_offset=start.as_ptr() as usize-self.source.as_ptr() as usize;
+        let end_offset=end.as_ptr() as usize+end.len()-self.source.as_ptr() as usize;
+        return Ok(&self.source[start_offset..end_offset]);
+    }
+    /// Parses a reference to a field of the loop's record, like `p.links.home`, and returns the
+    /// field's path. It is an error if no record has the field.
+    fn field_path(&mut self,var:&str,records:&[Value])->Result<'doc,&'doc str> {
+        let mut sp=self.subparser();
+        let name=sp.while_any(VAR_NAME_CHARS);
+        if name!=var||!sp.then(".")? {
+            sp.finish_error();
+            return Err(self.create_error(ErrorKind::ExpectedField(var.to_string()),true));
+        }
+        sp.finish();
+        let mut sp=self.subparser();
+        let path=sp.while_any(FIELD_PATH_CHARS);
+        sp.finish_error();
+        if path.len()==0 {
+            return Err(self.create_error(ErrorKind::ExpectedField(var.to_string()),true));
+        }
+        if records.len()>0&&!records.iter().any(|r|data::field(r,path).is_some()) {
+            return Err(self.create_error(ErrorKind::UndefinedField(format!("{}.{}",var,path)),true));
+        }
+        self.while_any(FIELD_PATH_CHARS);
+        return Ok(path);
+    }
+    /// Parses `for NAME in DATA ... { ... }` and repeats the items in it for each record. The
+    /// block's text has `$NAME.FIELD` replaced before it is parsed, so fields can be used anywhere.
+    fn for_loop(&mut self)->Result<'doc,Vec<Item>> {
+        let call=self.offset();
+        if !self.then("for")? {
+            return Err(self.create_error(ErrorKind::ExpectedFor,false));
+        }
+        self.skip_spaces();
+        let var=self.while_any(VAR_NAME_CHARS);
+        if var.len()==0 {
+            return Err(self.create_error(ErrorKind::ExpectedLoopVariable,true));
+        }
+        if !self.skip_spaces().then("in")? {
+            return Err(self.create_error(ErrorKind::ExpectedIn,true));
+        }
+        self.skip_spaces();
+        let mut sp=self.subparser();
+        let data_name=sp.while_any(VAR_NAME_CHARS);
+        sp.finish_error();
+        let Some(records)=self.state.data.get(data_name).cloned() else {
+            let help=did_you_mean(data_name,self.state.data.keys().map(String::as_str));
+            return Err(self.create_error_with_help(ErrorKind::UndefinedData(data_name.to_string()),data_name,help));
+        };
+        self.while_any(VAR_NAME_CHARS);
+        let mut records=records;
+        loop {
+            self.skip_whitespace();
+            if self.then("where")? {
+                self.skip_spaces();
+                let path=self.field_path(var,&records)?;
+                self.skip_spaces();
+                let Some(op)=COMPARISONS.iter().find(|op|self.test(op).unwrap_or(false)) else {
+                    records.retain(|r|data::truthy(data::field(r,path)));
+                    continue;
+                };
+                self.then(op)?;
+                self.skip_spaces();
+                let value=if self.test("\"")? {
+                    self.string()?
+                } else {
+                    let value=self.while_any(FIELD_VALUE_CHARS);
+                    if value.len()==0 {
+                        return Err(self.create_error(ErrorKind::ExpectedComparisonValue,true));
+                    }
+                    value.to_string()
+                };
+                let value=Value::String(value);
+                records.retain(|r|{
+                    let ordering=data::compare(data::field(r,path).unwrap_or(&Value::Null),&value);
+                    match *op {
+                        "=="=>ordering.is_eq(),
+                        "!="=>ordering.is_ne(),
+                        "<="=>ordering.is_le(),
+                        ">="=>ordering.is_ge(),
+                        "<"=>ordering.is_lt(),
+                        _=>ordering.is_gt(),
+                    }
+                });
+            } else if self.then("sort")? {
+                // Sort by the last key first, since the sort is stable.
+                let mut keys=Vec::new();
+                loop {
+                    self.skip_spaces();
+                    let path=self.field_path(var,&records)?;
+                    self.skip_spaces();
+                    let descending=if self.then("desc")? {
+                        true
+                    } else {
+                        self.then("asc")?;
+                        false
+                    };
+                    keys.push((path,descending));
+                    if !self.skip_spaces().then(",")? {
+                        break;
+                    }
+                }
+                for (path,descending) in keys.into_iter().rev() {
+                    records.sort_by(|a,b|{
+                        let ordering=data::compare(
+                            data::field(a,path).unwrap_or(&Value::Null),
+                            data::field(b,path).unwrap_or(&Value::Null),
+                        );
+                        if descending {ordering.reverse()} else {ordering}
+                    });
+                }
+            } else {
+                break;
+            }
+        }
+        if !self.then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedForBlockStart,true));
+        }
+        let body=self.block_body(ErrorKind::ExpectedForBlockEnd)?;
+        let mut items=Vec::new();
+        for record in records.iter() {
+            let expanded=substitute(body,true,|name|{
+                let (head,path)=name.split_once('.').unwrap_or((name,""));
+                if head!=var {
+                    return None;
+                } else if path.is_empty() {
+                    return Some(data::text(record));
+                }
+                // A record without the field gets nothing, since fields are often optional.
+                return Some(data::field(record,path).map(data::text).unwrap_or_default());
+            });
+            let errors=self.state.errors.len();
+            items.extend(self.expand(call,body,self.file,self.source,expanded)?);
+            // The same mistake would be reported again for every record.
+            if self.state.errors.len()>errors {
+                break;
+            }
+        }
+        return Ok(items);
+    }
+    /// Parses a `{ ... }` content block. The lines end at the first line that is indented less
+    /// than the first one, so they can hold anything, including braces.
+    fn content_block(&mut self)->Result<'doc,Vec<String>> {
+        if !self.then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedSectionContentBlockStart,true));
+        }
+        self.skip(NEWLINE);
+        let initial_indent=self.while_any(WHITESPACE);
+        let mut lines=vec![self.until_any(NEWLINE).to_string()];
+        self.then_any(NEWLINE)?;
+        loop {
+            if self.then(initial_indent)? {
+                lines.push(self.until_any(NEWLINE).to_string());
+                self.then_any(NEWLINE)?;
+                continue;
+            }
+            // Blank lines end paragraphs, so keep them even without the indentation.
+            let mut sp=self.subparser();
+            sp.skip(WHITESPACE);
+            if sp.then_any(NEWLINE)? {
+                sp.finish();
+                lines.push(String::new());
+                continue;
+            }
+            sp.finish_error();
+            break;
+        }
+        if !self.skip(WHITESPACE).then("}")? {
+            return Err(self.create_error(ErrorKind::ExpectedSectionContentBlockEnd,true));
+        }
+        return Ok(lines);
+    }
+    /// Checks that every extended style exists and fills in what each named style inherits.
+    fn resolve_styles(&mut self)->Result<'doc,()> {
+        let mut named_styles=std::mem::take(&mut self.state.named_styles);
+        let undefined=named_styles.iter().position(|named|{
+            match &named.extends {
+                Some((base,_))=>!named_styles.iter().any(|n|n.name==*base),
+                None=>false,
+            }
+        });
+        if let Some(i)=undefined {
+            let (_,error)=named_styles.swap_remove(i).extends.unwrap();
+            return Err(error);
+        }
+        for named in named_styles.iter() {
+            let mut style=named.style.clone();
+            let mut base=named.extends.as_ref().map(|(base,_)|*base);
+            // Circular inheritance is rejected as the styles are defined, so this always ends.
+            while let Some(name)=base {
+                let parent=named_styles.iter().find(|n|n.name==name).unwrap();
+                style=style.inherit(&parent.style);
+                base=parent.extends.as_ref().map(|(base,_)|*base);
+            }
+            self.state.styles.insert(named.name.to_string(),style);
+        }
+        return Ok(());
+    }
+}
+impl<'doc> Deref for DocumentParser<'doc> {
+    type Target=GenericParser<'doc,ParseError<'doc>>;
+    fn deref(&self)->&Self::Target {
+        &self.parser
+    }
+}
+impl<'doc> DerefMut for DocumentParser<'doc> {
+    fn deref_mut(&mut self)->&mut Self::Target {
+        &mut self.parser
+    }
+}
+
+
 pub trait Parser<'doc> {
-    fn into_document(self)->Result<'doc,Document>;
+    /// Parses the whole document. Errors don't stop it; the parts with errors are left out, and
+    /// the errors are returned with the rest of the document, along with any warnings. The last
+    /// value is whether it stopped at [`MAX_ERRORS`] with more left to report.
+    fn into_document(self)->(Document,Vec<SourceError<'doc>>,Vec<Warning>,bool);
     fn number(&mut self)->Result<'doc,f32>;
     fn name(&mut self)->Result<'doc,&'doc str>;
     fn size(&mut self)->Result<'doc,Size>;
     fn color(&mut self)->Result<'doc,Color>;
+    /// A double quoted string on one line. `\"` is a `"` and `\\` is a `\`, and other `\`s are
+    /// kept, so Windows paths work.
+    fn string(&mut self)->Result<'doc,String>;
+    /// Reads the rest of the line without trailing spaces or a comment after a space, replacing
+    /// `$name` with the variable's value. `$$` is a `$`, and so is a `$` that isn't before a letter
+    /// or `_`.
+    fn text(&mut self)->Result<'doc,String>;
+    /// Parses a `$name` reference to a variable of the given kind: `color`, `size`, or `text`.
+    /// Any variable can be used as text.
+    fn variable(&mut self,kind:&'static str)->Result<'doc,Var>;
+    fn vars(&mut self)->Result<'doc,Vec<(String,Var)>>;
     fn direction(&mut self)->Result<'doc,Direction>;
     fn page_size(&mut self)->Result<'doc,PageSize>;
+    fn paper(&mut self)->Result<'doc,Paper>;
+    fn orientation(&mut self)->Result<'doc,Orientation>;
     fn vertical_text_align(&mut self)->Result<'doc,VTextAlign>;
     fn horizontal_text_align(&mut self)->Result<'doc,HTextAlign>;
+    fn code_highlight(&mut self)->Result<'doc,CodeHighlight>;
+    fn footnote_placement(&mut self)->Result<'doc,FootnotePlacement>;
+    fn boolean(&mut self)->Result<'doc,bool>;
+    fn markdown_options(&mut self)->Result<'doc,MarkdownOptions>;
     fn section(&mut self)->Result<'doc,Section>;
     fn section_style(&mut self)->Result<'doc,SectionStyle>;
+    /// Parses `tags: [NAME, ...]`.
+    fn tags(&mut self)->Result<'doc,Vec<String>>;
+    /// Parses a `style NAME { ... }` definition from the metadata.
+    fn named_style(&mut self)->Result<'doc,()>;
+    /// Parses the keys of a style block after its `{`. `defining` is the name of the named style
+    /// being defined, which can `extends` another one. Other style blocks can `use` one.
+    fn style_block(&mut self,defining:Option<&'doc str>)->Result<'doc,(SectionStyle,Option<Extends<'doc>>)>;
     fn item(&mut self)->Result<'doc,Item>;
+    /// An item, or every item from an `include`, a component, or a `for` loop.
+    fn items(&mut self)->Result<'doc,Vec<Item>>;
+    /// Parses a `component NAME(PARAM, ...) { ... }` definition.
+    fn component(&mut self)->Result<'doc,()>;
+    fn toc(&mut self)->Result<'doc,Toc>;
     fn metadata(&mut self)->Result<'doc,Metadata>;
     fn page(&mut self)->Result<'doc,Page>;
+    /// A page, every page from an `include`, or nothing for a component definition.
+    fn pages(&mut self)->Result<'doc,Vec<Page>>;
     fn page_style(&mut self)->Result<'doc,PageStyle>;
+    /// Parses `data NAME = "FILE"` from the metadata and reads the file's records.
+    fn data(&mut self)->Result<'doc,()>;
 }
-impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
-    fn into_document(mut self)->Result<'doc,Document> {
-        let metadata=self.skip(EXT_WHITESPACE).metadata()?;
+impl<'doc> Parser<'doc> for DocumentParser<'doc> {
+    fn into_document(mut self)->(Document,Vec<SourceError<'doc>>,Vec<Warning>,bool) {
+        self.skip_whitespace();
+        let metadata=self.recover(|p|p.metadata()).unwrap_or_default();
         let mut pages=Vec::new();
-        while !self.skip(EXT_WHITESPACE).is_eof() {
-            pages.push(self.page()?);
+        while !self.skip_whitespace().is_eof()&&!self.too_many_errors() {
+            pages.extend(self.recover(|p|p.pages()).unwrap_or_default());
+        }
+        self.state.truncated|=!self.is_eof();
+        let mut warnings=Vec::new();
+        for (name,location) in self.state.defined_styles {
+            if !self.state.used_styles.contains(&name) {
+                warnings.push(Warning{kind:WarningKind::UnusedStyle(name),location:Some(location)});
+            }
+        }
+        let mut unused=self.state.components.into_values().filter(|c|!c.used).collect::<Vec<_>>();
+        unused.sort_by_key(|c|(c.file,c.name.as_ptr()));
+        for component in unused {
+            let location=Location{file:component.file.to_string(),offset:offset_in(component.source,component.name).unwrap_or(0)};
+            warnings.push(Warning{kind:WarningKind::UnusedComponent(component.name.to_string()),location:Some(location)});
         }
-        return Ok(Document{metadata,pages});
+        return (Document{metadata,pages},self.state.errors,warnings,self.state.truncated);
     }
     fn number(&mut self)->Result<'doc,f32> {
         const NUMBERS:&[&str]=&[
@@ -63,6 +788,10 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
         return Ok(name);
     }
     fn size(&mut self)->Result<'doc,Size> {
+        if self.test("$")? {
+            let Var::Size(size)=self.variable("size")? else {unreachable!()};
+            return Ok(size);
+        }
         let num=self.number()?;
         if self.then("in")? {
             return Ok(Size::Inches(num));
@@ -70,6 +799,8 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
             return Ok(Size::Points(num));
         } else if self.then("px")? {
             return Ok(Size::Pixels(num));
+        } else if self.then("mm")? {
+            return Ok(Size::Millimeters(num));
         } else if self.then("%")? {
             return Ok(Size::Percent(num));
         } else {
@@ -82,6 +813,10 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
             "a","b","c","d","e","f","A","B","C","D","E","F",
         ];
         const HEX_LOOKUP:&str="0123456789abcdef";
+        if self.test("$")? {
+            let Var::Color(color)=self.variable("color")? else {unreachable!()};
+            return Ok(color);
+        }
         if !self.then("#")? {
             return Err(self.create_error(ErrorKind::ExpectedColor,false));
         }
@@ -127,6 +862,114 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
             _=>return Err(self.create_error(ErrorKind::InvalidColorLength,false)),
         }
     }
+    fn string(&mut self)->Result<'doc,String> {
+        if !self.then("\"")? {
+            return Err(self.create_error(ErrorKind::ExpectedString,false));
+        }
+        let mut string=String::new();
+        loop {
+            string.push_str(self.until_any(&["\"","\\","\n","\r\n"]));
+            if self.is_eof() {
+                break;
+            } else if self.then("\\\"")? {
+                string.push('"');
+            } else if self.then("\\\\")?||self.then("\\")? {
+                string.push('\\');
+            } else {
+                break;
+            }
+        }
+        if self.is_eof()||!self.then("\"")? {
+            return Err(self.create_error(ErrorKind::UnterminatedString,true));
+        }
+        return Ok(string);
+    }
+    fn text(&mut self)->Result<'doc,String> {
+        let mut out=String::new();
+        loop {
+            out.push_str(self.until_any(&["$","\n","\r\n"," //"," /*"]));
+            if self.is_eof()||!self.test("$")? {
+                break;
+            }
+            if self.then("$$")? {
+                out.push('$');
+                continue;
+            }
+            // Only a `$` before a name is a variable, so prices like `$5` are kept.
+            let offset=self.offset();
+            if !self.source[offset+1..].starts_with(|c:char|c.is_ascii_alphabetic()||c=='_') {
+                self.then("$")?;
+                out.push('$');
+                continue;
+            }
+            let value=self.variable("text")?;
+            out.push_str(&value.text());
+        }
+        out.truncate(out.trim_end().len());
+        return Ok(out);
+    }
+    fn variable(&mut self,kind:&'static str)->Result<'doc,Var> {
+        let mut sp=self.subparser();
+        sp.then("$")?;
+        let name=sp.while_any(VAR_NAME_CHARS);
+        sp.finish_error();
+        if name.len()==0 {
+            return Err(self.create_error(ErrorKind::ExpectedVariableName,true));
+        }
+        let Some(value)=self.state.vars.get(name).cloned() else {
+            let help=did_you_mean(name,self.state.vars.keys().map(String::as_str));
+            return Err(self.create_error_with_help(ErrorKind::UndefinedVariable(name.to_string()),name,help));
+        };
+        if kind!="text"&&value.kind()!=kind {
+            return Err(self.create_error(ErrorKind::WrongVariableKind(name.to_string(),kind),true));
+        }
+        self.then("$")?;
+        self.while_any(VAR_NAME_CHARS);
+        return Ok(value);
+    }
+    fn vars(&mut self)->Result<'doc,Vec<(String,Var)>> {
+        if !self.then("vars")? {
+            return Err(self.create_error(ErrorKind::ExpectedVars,false));
+        }
+        if !self.skip_spaces().then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedVarsBlockStart,true));
+        }
+        let mut vars=Vec::new();
+        while !self.skip_whitespace().then("}")? {
+            let name=self.while_any(VAR_NAME_CHARS);
+            if name.len()==0 {
+                return Err(self.create_error(ErrorKind::ExpectedVariableName,true));
+            }
+            if self.state.vars.contains_key(name) {
+                return Err(self.create_error(ErrorKind::VariableAlreadyDefined(name.to_string()),true));
+            }
+            if !self.then(":")? {
+                return Err(self.create_error(ErrorKind::ExpectedColon,true));
+            }
+            self.skip_spaces();
+            // The kind is worked out from the value: a color, then a size, and anything else is text.
+            let value=if self.test("$")? {
+                self.variable("text")?
+            } else if self.test("#")? {
+                Var::Color(self.color()?)
+            } else {
+                let mut sp=self.subparser();
+                match sp.size() {
+                    Ok(size)=>{
+                        sp.finish();
+                        Var::Size(size)
+                    },
+                    Err(_)=>{
+                        sp.finish_error();
+                        Var::Text(self.text()?)
+                    },
+                }
+            };
+            self.state.vars.insert(name.to_string(),value.clone());
+            vars.push((name.to_string(),value));
+        }
+        return Ok(vars);
+    }
     fn direction(&mut self)->Result<'doc,Direction> {
         if self.then("Left")? {
             return Ok(Direction::Left);
@@ -141,7 +984,7 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
     }
     fn page_size(&mut self)->Result<'doc,PageSize> {
         if self.then(":")? {
-            self.skip(WHITESPACE);
+            self.skip_spaces();
             if self.then("PortraitLetter")? {
                 return Ok(PageSize::PortraitLetter);
             } else if self.then("LandscapeLetter")? {
@@ -149,25 +992,23 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
             } else if self.then("Webpage")? {
                 return Ok(PageSize::Webpage);
             }
+            return Ok(PageSize::Paper(self.paper()?));
         } else if self.then("{")? {
             let mut width=None;
             let mut height=None;
-            while !self.skip(EXT_WHITESPACE).then("}")? {
+            let mut defined=Defined::new();
+            while !self.skip_whitespace().then("}")? {
                 let name=self.name()?;
                 match name {
                     "width"=>{
-                        if width.is_some() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Custom page size width"),true));
-                        }
+                        self.define(&mut defined,"Custom page size width",&width)?;
                         width=Some(self.size()?);
                     },
                     "height"=>{
-                        if height.is_some() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Custom page size height"),true));
-                        }
+                        self.define(&mut defined,"Custom page size height",&height)?;
                         height=Some(self.size()?);
                     },
-                    _=>return Err(self.create_error(ErrorKind::ExpectedPageSize,true)),
+                    _=>return Err(self.unknown_key("page size",name,PAGE_SIZE_KEYS)),
                 }
             }
             let width=width.ok_or_else(||self.create_error(ErrorKind::ExpectedPageSizeWidth,true))?;
@@ -176,6 +1017,39 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
         }
         return Err(self.create_error(ErrorKind::ExpectedPageSize,true));
     }
+    fn paper(&mut self)->Result<'doc,Paper> {
+        use Paper::*;
+        const PAPERS:&[(&str,Paper)]=&[
+            ("Letter",Letter),
+            ("Legal",Legal),
+            ("Tabloid",Tabloid),
+            ("A3",A3),
+            ("A4",A4),
+            ("A5",A5),
+            ("A6",A6),
+            ("B5",B5),
+            ("EnvelopeDL",EnvelopeDL),
+            ("EnvelopeC5",EnvelopeC5),
+            ("EnvelopeC6",EnvelopeC6),
+            ("Envelope10",Envelope10),
+            ("IndexCard",IndexCard),
+            ("BusinessCard",BusinessCard),
+        ];
+        for (name,paper) in PAPERS {
+            if self.then(name)? {
+                return Ok(*paper);
+            }
+        }
+        return Err(self.create_error(ErrorKind::ExpectedPageSize,true));
+    }
+    fn orientation(&mut self)->Result<'doc,Orientation> {
+        if self.then("Portrait")? {
+            return Ok(Orientation::Portrait);
+        } else if self.then("Landscape")? {
+            return Ok(Orientation::Landscape);
+        }
+        return Err(self.create_error(ErrorKind::ExpectedOrientation,true));
+    }
     fn vertical_text_align(&mut self)->Result<'doc,VTextAlign> {
         if self.then("Top")? {
             return Ok(VTextAlign::Top);
@@ -196,66 +1070,194 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
         }
         return Err(self.create_error(ErrorKind::ExpectedTextAlign,true));
     }
+    fn code_highlight(&mut self)->Result<'doc,CodeHighlight> {
+        if self.then("Inline")? {
+            return Ok(CodeHighlight::Inline);
+        } else if self.then("Classes")? {
+            return Ok(CodeHighlight::Classes);
+        }
+        return Err(self.create_error(ErrorKind::ExpectedCodeHighlight,true));
+    }
+    fn footnote_placement(&mut self)->Result<'doc,FootnotePlacement> {
+        if self.then("Page")? {
+            return Ok(FootnotePlacement::Page);
+        } else if self.then("Document")? {
+            return Ok(FootnotePlacement::Document);
+        }
+        return Err(self.create_error(ErrorKind::ExpectedFootnotePlacement,true));
+    }
+    fn boolean(&mut self)->Result<'doc,bool> {
+        if self.then("true")? {
+            return Ok(true);
+        } else if self.then("false")? {
+            return Ok(false);
+        }
+        return Err(self.create_error(ErrorKind::ExpectedBoolean,true));
+    }
+    fn markdown_options(&mut self)->Result<'doc,MarkdownOptions> {
+        if !self.then("markdown")? {
+            return Err(self.create_error(ErrorKind::ExpectedMarkdownOptions,false));
+        }
+        if !self.skip_spaces().then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedMarkdownOptionsBlockStart,true));
+        }
+        let mut options=MarkdownOptions::default();
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            self.recover(|p|{
+                let name=p.name()?;
+                if !p.then(":")? {
+                    return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                }
+                p.skip_spaces();
+                let (option,item)=match name {
+                    "tables"=>(&mut options.tables,"Markdown/tables"),
+                    "tasklists"=>(&mut options.tasklists,"Markdown/tasklists"),
+                    "smart_punctuation"=>(&mut options.smart_punctuation,"Markdown/smart punctuation"),
+                    "heading_attributes"=>(&mut options.heading_attributes,"Markdown/heading attributes"),
+                    _=>return Err(p.unknown_key("markdown",name,MARKDOWN_KEYS)),
+                };
+                p.define(&mut defined,item,option)?;
+                *option=Some(p.boolean()?);
+                return Ok(());
+            });
+        }
+        return Ok(options);
+    }
     fn section(&mut self)->Result<'doc,Section> {
         if !self.then("section")? {
             return Err(self.create_error(ErrorKind::ExpectedSection,false));
         }
-        if !self.skip(WHITESPACE).then("{")? {
+        if !self.skip_spaces().then("{")? {
             return Err(self.create_error(ErrorKind::ExpectedSectionBlockStart,true));
         }
+        let errors=self.state.errors.len();
         let mut style=None;
         let mut content=None;
-        while !self.skip(EXT_WHITESPACE).then("}")? {
-            let mut sp=self.subparser();
-            let name=sp.name()?;
-            match name {
-                "style"=>{
-                    sp.finish_error();
-                    if style.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section/style"),true));
-                    }
-                    style=Some(self.section_style()?);
-                },
-                "content"=>{
-                    sp.finish();
-                    if style.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section/style"),true));
-                    }
-                    if !self.skip(WHITESPACE).then("{")? {
-                        return Err(self.create_error(ErrorKind::ExpectedSectionContentBlockStart,true));
-                    }
-                    self.skip(NEWLINE);
-                    let initial_indent=self.while_any(WHITESPACE);
-                    let mut lines=vec![self.until_any(NEWLINE).to_string()];
-                    self.then_any(NEWLINE)?;
-                    loop {
-                        if !self.then(initial_indent)? {
-                            break;
+        let mut location=None;
+        let mut tags=None;
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            self.recover(|p|{
+                let key=p.location();
+                let mut sp=p.subparser();
+                let name=sp.name()?;
+                match name {
+                    "style"=>{
+                        sp.finish_error();
+                        p.define(&mut defined,"Section/style",&style)?;
+                        style=Some(p.section_style()?);
+                    },
+                    "tags"=>{
+                        sp.finish_error();
+                        p.define(&mut defined,"Section/tags",&tags)?;
+                        tags=Some(p.tags()?);
+                    },
+                    "content"=>{
+                        sp.finish();
+                        p.define(&mut defined,"Section/content",&content)?;
+                        p.skip_spaces();
+                        content=Some(p.content_block()?);
+                        location=Some(key);
+                    },
+                    "content_file"=>{
+                        sp.finish();
+                        p.define(&mut defined,"Section/content",&content)?;
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
                         }
-                        lines.push(self.until_any(NEWLINE).to_string());
-                        self.skip(NEWLINE);
-                    }
-                    if !self.skip(WHITESPACE).then("}")? {
-                        return Err(self.create_error(ErrorKind::ExpectedSectionContentBlockEnd,true));
-                    }
-                    content=Some(lines);
-                },
-                _=>{
-                    sp.finish_error();
-                    return Err(self.create_error(ErrorKind::ExpectedSection,true));
-                },
-            }
+                        p.skip_spaces();
+                        let mut sp=p.subparser();
+                        let relative=sp.string()?;
+                        sp.finish_error();
+                        let path=p.relative_path(&relative);
+                        let markdown=match p.sources.load(&path) {
+                            Ok((_,markdown))=>markdown,
+                            Err(e)=>return Err(p.create_error(ErrorKind::ReadFile(path.display().to_string(),e.to_string()),true)),
+                        };
+                        p.string()?;
+                        content=Some(markdown.lines().map(str::to_string).collect());
+                        location=Some(key);
+                    },
+                    _=>{
+                        sp.finish_error();
+                        return Err(p.unknown_key("section",name,SECTION_KEYS));
+                    },
+                }
+                return Ok(());
+            });
+        }
+        // The content may be missing because of an error that was already reported.
+        if content.is_none()&&self.state.errors.len()>errors {
+            content=Some(Vec::new());
         }
         let content=content.ok_or_else(||self.create_error(ErrorKind::ExpectedSectionContent,true))?;
-        return Ok(Section{content,style});
+        // Merged like the renderers do, so they agree on which links are references.
+        let options=self.state.markdown.merge(style.as_ref().and_then(|s|s.markdown.as_ref())).options();
+        if let Some((label,line))=undefined_link_reference(&content,options) {
+            return Err(self.create_error(ErrorKind::UndefinedLinkReference(label,line),true));
+        }
+        return Ok(Section{content,style,tags:tags.unwrap_or_default(),location});
+    }
+    fn tags(&mut self)->Result<'doc,Vec<String>> {
+        if !self.then("tags")? {
+            return Err(self.create_error(ErrorKind::ExpectedTags,false));
+        }
+        if !self.then(":")? {
+            return Err(self.create_error(ErrorKind::ExpectedColon,true));
+        }
+        if !self.skip_spaces().then("[")? {
+            return Err(self.create_error(ErrorKind::ExpectedTagListStart,true));
+        }
+        let mut tags=Vec::new();
+        while !self.skip_spaces().then("]")? {
+            if tags.len()>0&&!self.then(",")? {
+                return Err(self.create_error(ErrorKind::ExpectedTagListEnd,true));
+            }
+            self.skip_spaces();
+            let tag=self.while_any(TAG_CHARS);
+            if tag.len()==0 {
+                return Err(self.create_error(ErrorKind::ExpectedTag,true));
+            }
+            tags.push(tag.to_string());
+        }
+        return Ok(tags);
     }
     fn section_style(&mut self)->Result<'doc,SectionStyle> {
         if !self.then("style")? {
             return Err(self.create_error(ErrorKind::ExpectedSectionStyle,false));
         }
-        if !self.skip(WHITESPACE).then("{")? {
+        if !self.skip_spaces().then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedSectionStyleBlockStart,true));
+        }
+        let (style,_)=self.style_block(None)?;
+        return Ok(style);
+    }
+    fn named_style(&mut self)->Result<'doc,()> {
+        if !self.then("style")? {
+            return Err(self.create_error(ErrorKind::ExpectedSectionStyle,false));
+        }
+        self.skip_spaces();
+        let mut sp=self.subparser();
+        let name=sp.name()?;
+        sp.finish_error();
+        if let Some(named)=self.state.named_styles.iter().find(|named|named.name==name) {
+            let labels=Label::at(self.file,self.source,named.name,"first defined here").into_iter().collect();
+            return Err(self.create_diagnostic(ParseError::new(ErrorKind::StyleAlreadyDefined(name.to_string()),None,labels,None),true));
+        }
+        self.name()?;
+        if !self.skip_spaces().then("{")? {
             return Err(self.create_error(ErrorKind::ExpectedSectionStyleBlockStart,true));
         }
+        let (style,extends)=self.style_block(Some(name))?;
+        let location=Location{file:self.file.to_string(),offset:offset_in(self.source,name).unwrap_or(0)};
+        self.state.defined_styles.push((name.to_string(),location));
+        self.state.named_styles.push(NamedStyle{name,style,extends});
+        return Ok(());
+    }
+    fn style_block(&mut self,defining:Option<&'doc str>)->Result<'doc,(SectionStyle,Option<Extends<'doc>>)> {
+        let mut base=None;
+        let mut extends=None;
         let mut width=None;
         let mut height=None;
         let mut align=None;
@@ -266,83 +1268,116 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
         let mut margin:Option<SizedSides>=None;
         let mut vertical_text_align=None;
         let mut horizontal_text_align=None;
-        while !self.skip(EXT_WHITESPACE).then("}")? {
+        let mut markdown=None;
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            if self.test("markdown")? {
+                self.define(&mut defined,"Section style/markdown",&markdown)?;
+                markdown=Some(self.markdown_options()?);
+                continue;
+            }
             let name=self.name()?;
             if !self.then(":")? {
                 return Err(self.create_error(ErrorKind::ExpectedColon,true));
             }
-            self.skip(WHITESPACE);
+            self.skip_spaces();
             match name {
-                "width"=>{
-                    if width.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/width"),true));
+                "use" if defining.is_none()=>{
+                    self.define(&mut defined,"Section style/use",&base)?;
+                    let mut sp=self.subparser();
+                    let style_name=sp.until_any(TEXT_END).trim_end();
+                    sp.finish_error();
+                    let Some(style)=self.state.styles.get(style_name).cloned() else {
+                        let help=did_you_mean(style_name,self.state.styles.keys().map(String::as_str));
+                        return Err(self.create_error_with_help(ErrorKind::UndefinedStyle(style_name.to_string()),style_name,help));
+                    };
+                    self.state.used_styles.insert(style_name.to_string());
+                    self.until_any(TEXT_END);
+                    base=Some(style);
+                },
+                "extends" if defining.is_some()=>{
+                    self.define(&mut defined,"Style/extends",&extends)?;
+                    let defining=defining.unwrap();
+                    let mut sp=self.subparser();
+                    let style_name=sp.until_any(TEXT_END).trim_end();
+                    sp.finish_error();
+                    // The styles defined so far can't already form a loop, so one would have to
+                    // come back around to this style.
+                    let mut chain=vec![defining,style_name];
+                    let mut current=style_name;
+                    while current!=defining {
+                        let parent=self.state.named_styles.iter()
+                            .find(|named|named.name==current)
+                            .and_then(|named|named.extends.as_ref());
+                        let Some((next,_))=parent else {
+                            break;
+                        };
+                        chain.push(next);
+                        current=next;
                     }
+                    if current==defining {
+                        return Err(self.create_error(ErrorKind::CircularStyle(chain.join(" -> ")),true));
+                    }
+                    let error=self.create_error(ErrorKind::UndefinedStyle(style_name.to_string()),true);
+                    self.until_any(TEXT_END);
+                    self.state.used_styles.insert(style_name.to_string());
+                    extends=Some((style_name,error));
+                },
+                "width"=>{
+                    self.define(&mut defined,"Section style/width",&width)?;
                     width=Some(self.size()?);
                 },
                 "height"=>{
-                    if height.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/height"),true));
-                    }
+                    self.define(&mut defined,"Section style/height",&height)?;
                     height=Some(self.size()?);
                 },
                 "vertical_text_align"=>{
-                    if vertical_text_align.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/vertical text align"),true));
-                    }
+                    self.define(&mut defined,"Section style/vertical text align",&vertical_text_align)?;
                     vertical_text_align=Some(self.vertical_text_align()?);
                 },
                 "horizontal_text_align"=>{
-                    if horizontal_text_align.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/horizontal text align"),true));
-                    }
+                    self.define(&mut defined,"Section style/horizontal text align",&horizontal_text_align)?;
                     horizontal_text_align=Some(self.horizontal_text_align()?);
                 },
                 "align"=>{
-                    if align.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/align"),true));
-                    }
+                    self.define(&mut defined,"Section style/align",&align)?;
                     align=Some(self.direction()?);
                 },
                 "font"=>{
-                    if font.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/font"),true));
-                    }
-                    font=Some(self.until_any(NEWLINE).to_string());
+                    self.define(&mut defined,"Section style/font",&font)?;
+                    font=Some(self.text()?);
                 },
                 "font_size"=>{
-                    if font_size.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/font size"),true));
-                    }
+                    self.define(&mut defined,"Section style/font size",&font_size)?;
                     font_size=Some(self.size()?);
                 },
                 "text_color"=>{
-                    if text_color.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/text color"),true));
-                    }
+                    self.define(&mut defined,"Section style/text color",&text_color)?;
                     text_color=Some(self.color()?);
                 },
                 "background_color"=>{
-                    if background_color.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/background color"),true));
-                    }
+                    self.define(&mut defined,"Section style/background color",&background_color)?;
                     background_color=Some(self.color()?);
                 },
                 "margin"=>{
+                    self.mark_defined(&mut defined,"Page style/margin");
                     if let Some(margin)=&mut margin {
                         if margin.is_individual() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin-*"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin-*"));
                         }
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                        return Err(self.already_defined(&defined,"Page style/margin"));
                     } else {
                         margin=Some(SizedSides::All(self.size()?));
                     }
                 },
                 "margin_left"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_left");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_left_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_left"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_left"));
                         }
                         margin.set_left(self.size()?);
                     } else {
@@ -355,11 +1390,13 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_right"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_right");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_right_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_right"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_right"));
                         }
                         margin.set_right(self.size()?);
                     } else {
@@ -372,11 +1409,13 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_top"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_top");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_top_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_top"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_top"));
                         }
                         margin.set_top(self.size()?);
                     } else {
@@ -389,11 +1428,13 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_bottom"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_bottom");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_bottom_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_bottom"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_bottom"));
                         }
                         margin.set_bottom(self.size()?);
                     } else {
@@ -405,216 +1446,486 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                         });
                     }
                 },
-                _=>return Err(self.create_error(ErrorKind::ExpectedSectionStyle,true)),
+                _=>return Err(self.unknown_key("section style",name,SECTION_STYLE_KEYS)),
             }
         }
-        return Ok(SectionStyle{width,height,align,font,font_size,text_color,background_color,margin,vertical_text_align,horizontal_text_align});
+        let style=SectionStyle{width,height,align,font,font_size,text_color,background_color,margin,vertical_text_align,horizontal_text_align,markdown};
+        match base {
+            Some(base)=>Ok((style.inherit(&base),extends)),
+            None=>Ok((style,extends)),
+        }
     }
     fn item(&mut self)->Result<'doc,Item> {
         if self.then("vertical")? {
-            if !self.skip(WHITESPACE).then("{")? {
+            if !self.skip_spaces().then("{")? {
                 return Err(self.create_error(ErrorKind::ExpectedItemBlockStart,true));
             }
             let mut items=Vec::new();
             let mut style=None;
-            while !self.skip(EXT_WHITESPACE).then("}")? {
-                let mut sp=self.subparser();
-                let name=sp.name()?;
-                sp.finish_error();
-                match name {
-                    "style"=>{
-                        if style.is_some() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Item/style"),true));
-                        }
-                        style=Some(self.section_style()?);
-                    },
-                    _=>items.push(self.item()?),
-                }
+            let mut tags=None;
+            let mut defined=Defined::new();
+            while !self.skip_whitespace().then("}")? {
+                self.recover(|p|{
+                    let mut sp=p.subparser();
+                    let name=sp.name()?;
+                    sp.finish_error();
+                    match name {
+                        "style"=>{
+                            p.define(&mut defined,"Item/style",&style)?;
+                            style=Some(p.section_style()?);
+                        },
+                        "tags"=>{
+                            p.define(&mut defined,"Item/tags",&tags)?;
+                            tags=Some(p.tags()?);
+                        },
+                        _=>items.extend(p.items()?),
+                    }
+                    return Ok(());
+                });
             }
-            return Ok(Item::Vertical{items,style});
+            return Ok(Item::Vertical{items,style,tags:tags.unwrap_or_default()});
         } else if self.then("horizontal")? {
-            if !self.skip(WHITESPACE).then("{")? {
+            if !self.skip_spaces().then("{")? {
                 return Err(self.create_error(ErrorKind::ExpectedItemBlockStart,true));
             }
             let mut items=Vec::new();
             let mut style=None;
-            while !self.skip(EXT_WHITESPACE).then("}")? {
-                let mut sp=self.subparser();
+            let mut tags=None;
+            let mut defined=Defined::new();
+            while !self.skip_whitespace().then("}")? {
+                self.recover(|p|{
+                    let mut sp=p.subparser();
+                    let name=sp.name()?;
+                    sp.finish_error();
+                    match name {
+                        "style"=>{
+                            p.define(&mut defined,"Item/style",&style)?;
+                            style=Some(p.section_style()?);
+                        },
+                        "tags"=>{
+                            p.define(&mut defined,"Item/tags",&tags)?;
+                            tags=Some(p.tags()?);
+                        },
+                        _=>items.extend(p.items()?),
+                    }
+                    return Ok(());
+                });
+            }
+            return Ok(Item::Horizontal{items,style,tags:tags.unwrap_or_default()});
+        } else if self.test("section")? {
+            return Ok(Item::Section(self.section()?));
+        } else if self.test("toc")? {
+            return Ok(Item::Toc(self.toc()?));
+        }
+        let mut sp=self.subparser();
+        let name=sp.while_any(VAR_NAME_CHARS);
+        sp.finish_error();
+        let help=did_you_mean(name,ITEM_KINDS.iter().copied());
+        return Err(self.create_diagnostic(ParseError::new(ErrorKind::ExpectedItem,offset_in(self.source,name),Vec::new(),help),false));
+    }
+    fn items(&mut self)->Result<'doc,Vec<Item>> {
+        if self.test("include")? {
+            return self.include(|parser|parser.items());
+        }
+        let mut sp=self.subparser();
+        let name=sp.while_any(VAR_NAME_CHARS);
+        let is_component=sp.test("(").unwrap_or(false);
+        sp.finish_error();
+        if name=="for" {
+            return self.for_loop();
+        } else if is_component&&!KEYWORDS.contains(&name) {
+            return self.expand_component();
+        }
+        return Ok(vec![self.item()?]);
+    }
+    fn component(&mut self)->Result<'doc,()> {
+        if !self.then("component")? {
+            return Err(self.create_error(ErrorKind::ExpectedComponent,false));
+        }
+        self.skip_spaces();
+        let name=self.while_any(VAR_NAME_CHARS);
+        if name.len()==0 {
+            return Err(self.create_error(ErrorKind::ExpectedComponentName,true));
+        }
+        if KEYWORDS.contains(&name) {
+            return Err(self.create_error(ErrorKind::ReservedComponentName(name.to_string()),true));
+        }
+        if let Some(component)=self.state.components.get(name) {
+            let labels=Label::at(component.file,component.source,component.name,"first defined here").into_iter().collect();
+            let offset=offset_in(self.source,name);
+            return Err(self.create_diagnostic(ParseError::new(ErrorKind::ComponentAlreadyDefined(name.to_string()),offset,labels,None),true));
+        }
+        if !self.skip_spaces().then("(")? {
+            return Err(self.create_error(ErrorKind::ExpectedParameters,true));
+        }
+        let mut params=Vec::new();
+        while !self.skip_whitespace().then(")")? {
+            if params.len()>0&&!self.then(",")? {
+                return Err(self.create_error(ErrorKind::ExpectedParameters,true));
+            }
+            self.skip_whitespace();
+            let param=self.while_any(VAR_NAME_CHARS);
+            if param.len()==0 {
+                return Err(self.create_error(ErrorKind::ExpectedParameters,true));
+            }
+            params.push(param);
+        }
+        if !self.skip_spaces().then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedComponentBlockStart,true));
+        }
+        let body=self.block_body(ErrorKind::ExpectedComponentBlockEnd)?;
+        self.state.components.insert(name.to_string(),Component{name,used:false,params,body,file:self.file,source:self.source});
+        return Ok(());
+    }
+    fn toc(&mut self)->Result<'doc,Toc> {
+        if !self.then("toc")? {
+            return Err(self.create_error(ErrorKind::ExpectedToc,false));
+        }
+        if !self.skip_spaces().then("{")? {
+            return Err(self.create_error(ErrorKind::ExpectedTocBlockStart,true));
+        }
+        let mut depth=None;
+        let mut pages=None;
+        let mut style=None;
+        let mut tags=None;
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            self.recover(|p|{
+                let mut sp=p.subparser();
                 let name=sp.name()?;
-                sp.finish_error();
                 match name {
                     "style"=>{
-                        if style.is_some() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Item/style"),true));
+                        sp.finish_error();
+                        p.define(&mut defined,"Toc/style",&style)?;
+                        style=Some(p.section_style()?);
+                    },
+                    "tags"=>{
+                        sp.finish_error();
+                        p.define(&mut defined,"Toc/tags",&tags)?;
+                        tags=Some(p.tags()?);
+                    },
+                    "depth"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                        }
+                        p.skip_spaces();
+                        p.define(&mut defined,"Toc/depth",&depth)?;
+                        let num=p.number()?;
+                        if num.fract()!=0.0||num<1.0||num>6.0 {
+                            return Err(p.create_error(ErrorKind::InvalidTocDepth,true));
+                        }
+                        depth=Some(num as u8);
+                    },
+                    "pages"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
                         }
-                        style=Some(self.section_style()?);
+                        p.define(&mut defined,"Toc/pages",&pages)?;
+                        let mut list=Vec::new();
+                        loop {
+                            p.skip_spaces();
+                            let num=p.number()?;
+                            if num.fract()!=0.0||num<1.0 {
+                                return Err(p.create_error(ErrorKind::ExpectedPageNumber,true));
+                            }
+                            list.push(num as usize);
+                            if !p.skip_spaces().then(",")? {
+                                break;
+                            }
+                        }
+                        pages=Some(list);
+                    },
+                    _=>{
+                        sp.finish_error();
+                        return Err(p.unknown_key("table of contents",name,TOC_KEYS));
                     },
-                    _=>items.push(self.item()?),
                 }
-            }
-            return Ok(Item::Horizontal{items,style});
-        } else if self.test("section")? {
-            return Ok(Item::Section(self.section()?));
+                return Ok(());
+            });
         }
-        return Err(self.create_error(ErrorKind::ExpectedItem,false));
+        return Ok(Toc{depth,pages,style,tags:tags.unwrap_or_default()});
     }
     fn metadata(&mut self)->Result<'doc,Metadata> {
         if !self.then("metadata")? {
             return Err(self.create_error(ErrorKind::ExpectedMetadata,false));
         }
-        if !self.skip(WHITESPACE).then("{")? {
+        if !self.skip_spaces().then("{")? {
             return Err(self.create_error(ErrorKind::ExpectedMetadataBlockStart,true));
         }
         let mut title=None;
         let mut page_style=None;
-        while !self.skip(EXT_WHITESPACE).then("}")? {
-            let mut sp=self.subparser();
-            let name=sp.name()?;
-            match name {
-                "title"=>{
-                    sp.finish();
-                    if !self.then(":")? {
-                        return Err(self.create_error(ErrorKind::ExpectedColon,true));
-                    }
-                    self.skip(WHITESPACE);
-                    if title.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Metadata/title"),true));
-                    }
-                    title=Some(self.until_any(NEWLINE).to_string());
-                },
-                "style"=>{
-                    sp.finish_error();
-                    if page_style.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Metadata/style"),true));
-                    }
-                    page_style=Some(self.page_style()?);
-                },
-                _=>{
-                    sp.finish_error();
-                    return Err(self.create_error(ErrorKind::ExpectedMetadata,true));
-                },
-            }
+        let mut code_theme=None;
+        let mut code_highlight=None;
+        let mut markdown=None;
+        let mut footnotes=None;
+        let mut vars=None;
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            self.recover(|p|{
+                let mut sp=p.subparser();
+                let name=sp.name()?;
+                match name {
+                    "title"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                        }
+                        p.skip_spaces();
+                        p.define(&mut defined,"Metadata/title",&title)?;
+                        title=Some(p.text()?);
+                    },
+                    "code_theme"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                        }
+                        p.skip_spaces();
+                        p.define(&mut defined,"Metadata/code theme",&code_theme)?;
+                        let theme=p.until_any(TEXT_END).trim_end();
+                        if !THEMES.contains(&theme) {
+                            let help=did_you_mean(theme,THEMES.iter().copied());
+                            return Err(p.create_error_with_help(ErrorKind::UnknownCodeTheme(theme.to_string()),theme,help));
+                        }
+                        code_theme=Some(theme.to_string());
+                    },
+                    "code_highlight"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                        }
+                        p.skip_spaces();
+                        p.define(&mut defined,"Metadata/code highlight",&code_highlight)?;
+                        code_highlight=Some(p.code_highlight()?);
+                    },
+                    "footnotes"=>{
+                        sp.finish();
+                        if !p.then(":")? {
+                            return Err(p.create_error(ErrorKind::ExpectedColon,true));
+                        }
+                        p.skip_spaces();
+                        p.define(&mut defined,"Metadata/footnotes",&footnotes)?;
+                        footnotes=Some(p.footnote_placement()?);
+                    },
+                    "style"=>{
+                        sp.finish_error();
+                        // `style {` is the page style, and `style NAME {` defines a named style.
+                        let mut sp=p.subparser();
+                        sp.then("style")?;
+                        if !sp.skip_spaces().test("{")? {
+                            sp.finish_error();
+                            p.named_style()?;
+                            return Ok(());
+                        }
+                        sp.finish_error();
+                        p.define(&mut defined,"Metadata/style",&page_style)?;
+                        page_style=Some(p.page_style()?);
+                    },
+                    "markdown"=>{
+                        sp.finish_error();
+                        p.define(&mut defined,"Metadata/markdown",&markdown)?;
+                        let options=p.markdown_options()?;
+                        p.state.markdown=options.clone();
+                        markdown=Some(options);
+                    },
+                    "data"=>{
+                        sp.finish_error();
+                        p.data()?;
+                    },
+                    "vars"=>{
+                        sp.finish_error();
+                        p.define(&mut defined,"Metadata/vars",&vars)?;
+                        vars=Some(p.vars()?);
+                    },
+                    _=>{
+                        sp.finish_error();
+                        return Err(p.unknown_key("metadata",name,METADATA_KEYS));
+                    },
+                }
+                return Ok(());
+            });
+        }
+        // These are kept instead of returned, since the rest of the metadata is still good. A title
+        // whose value failed was already reported.
+        if title.is_none()&&!defined.contains_key("Metadata/title") {
+            let error=self.create_error(ErrorKind::ExpectedMetadataTitle,true);
+            self.record(error);
+        }
+        if let Err(error)=self.resolve_styles() {
+            self.record(error);
+        }
+        let title=title.unwrap_or_default();
+        let vars=vars.unwrap_or_default();
+        return Ok(Metadata{title,page_style,code_theme,code_highlight,markdown,footnotes,vars});
+    }
+    fn pages(&mut self)->Result<'doc,Vec<Page>> {
+        if self.test("include")? {
+            return self.include(|parser|parser.pages());
+        } else if self.test("component")? {
+            self.component()?;
+            return Ok(Vec::new());
         }
-        let title=title.ok_or_else(||self.create_error(ErrorKind::ExpectedMetadataTitle,true))?;
-        return Ok(Metadata{title,page_style});
+        return Ok(vec![self.page()?]);
     }
     fn page(&mut self)->Result<'doc,Page> {
         if !self.then("page")? {
             return Err(self.create_error(ErrorKind::ExpectedPage,false));
         }
-        if !self.skip(WHITESPACE).then("{")? {
+        if !self.skip_spaces().then("{")? {
             return Err(self.create_error(ErrorKind::ExpectedPageBlockStart,true));
         }
         let mut items=Vec::new();
         let mut style=None;
-        while !self.skip(EXT_WHITESPACE).then("}")? {
-            let mut sp=self.subparser();
-            let name=sp.name()?;
-            sp.finish_error();
-            match name {
-                "style"=>{
-                    if style.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page/style"),true));
-                    }
-                    style=Some(self.page_style()?);
-                },
-                _=>{
-                    match self.item() {
-                        Ok(item)=>{
-                            items.push(item);
-                            continue;
-                        },
-                        Err(e)=>{
-                            if e.important {
-                                return Err(e);
-                            }
-                        },
-                    }
-                    return Err(self.create_error(ErrorKind::ExpectedPage,true));
-                },
-            }
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
+            self.recover(|p|{
+                let mut sp=p.subparser();
+                let name=sp.name()?;
+                sp.finish_error();
+                match name {
+                    "style"=>{
+                        p.define(&mut defined,"Page/style",&style)?;
+                        style=Some(p.page_style()?);
+                    },
+                    _=>{
+                        match p.items() {
+                            Ok(new_items)=>{
+                                items.extend(new_items);
+                                return Ok(());
+                            },
+                            Err(e)=>{
+                                if e.important {
+                                    return Err(e);
+                                }
+                            },
+                        }
+                        let help=did_you_mean(name,ITEM_KINDS.iter().copied());
+                        return Err(p.create_error_with_help(ErrorKind::ExpectedItem,name,help));
+                    },
+                }
+                return Ok(());
+            });
         }
         return Ok(Page{items,style});
     }
+    fn data(&mut self)->Result<'doc,()> {
+        if !self.then("data")? {
+            return Err(self.create_error(ErrorKind::ExpectedData,false));
+        }
+        self.skip_spaces();
+        let mut sp=self.subparser();
+        let name=sp.while_any(VAR_NAME_CHARS);
+        sp.finish_error();
+        if name.len()==0 {
+            return Err(self.create_error(ErrorKind::ExpectedDataName,true));
+        }
+        if self.state.data.contains_key(name) {
+            return Err(self.create_error(ErrorKind::DataAlreadyDefined(name.to_string()),true));
+        }
+        self.while_any(VAR_NAME_CHARS);
+        if !self.skip_spaces().then("=")? {
+            return Err(self.create_error(ErrorKind::ExpectedEquals,true));
+        }
+        self.skip_spaces();
+        let mut sp=self.subparser();
+        let relative=sp.string()?;
+        sp.finish_error();
+        let path=self.relative_path(&relative);
+        let (file,contents)=match self.sources.load(&path) {
+            Ok(loaded)=>loaded,
+            Err(e)=>return Err(self.create_error(ErrorKind::ReadFile(path.display().to_string(),e.to_string()),true)),
+        };
+        let records=match data::parse(file,contents) {
+            Ok(records)=>records,
+            Err(e)=>return Err(self.create_error(ErrorKind::InvalidData(file.to_string(),e),true)),
+        };
+        self.string()?;
+        self.state.data.insert(name.to_string(),records);
+        return Ok(());
+    }
     fn page_style(&mut self)->Result<'doc,PageStyle> {
         if !self.then("style")? {
             return Err(self.create_error(ErrorKind::ExpectedPageStyle,false));
         }
-        if !self.skip(WHITESPACE).then("{")? {
+        if !self.skip_spaces().then("{")? {
             return Err(self.create_error(ErrorKind::ExpectedPageStyleBlockStart,true));
         }
         let mut page_size=None;
+        let mut orientation=None;
         let mut text_color=None;
         let mut background_color=None;
         let mut margin:Option<SizedSides>=None;
         let mut vertical_text_align=None;
         let mut horizontal_text_align=None;
-        while !self.skip(EXT_WHITESPACE).then("}")? {
+        let mut defined=Defined::new();
+        while !self.skip_whitespace().then("}")? {
             let name=self.name()?;
             match name {
                 "page_size"=>{
-                    if page_size.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/page size"),true));
-                    }
-                    self.skip(WHITESPACE);
+                    self.define(&mut defined,"Page style/page size",&page_size)?;
+                    self.skip_spaces();
                     page_size=Some(self.page_size()?);
                 },
-                "text_color"=>{
+                "orientation"=>{
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
-                    if text_color.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/text color"),true));
+                    self.skip_spaces();
+                    self.define(&mut defined,"Page style/orientation",&orientation)?;
+                    orientation=Some(self.orientation()?);
+                },
+                "text_color"=>{
+                    if !self.then(":")? {
+                        return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
+                    self.skip_spaces();
+                    self.define(&mut defined,"Page style/text color",&text_color)?;
                     text_color=Some(self.color()?);
                 },
                 "background_color"=>{
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
-                    if background_color.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/background color"),true));
-                    }
+                    self.skip_spaces();
+                    self.define(&mut defined,"Page style/background color",&background_color)?;
                     background_color=Some(self.color()?);
                 },
                 "vertical_text_align"=>{
-                    if vertical_text_align.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/vertical text align"),true));
-                    }
+                    self.define(&mut defined,"Section style/vertical text align",&vertical_text_align)?;
                     vertical_text_align=Some(self.vertical_text_align()?);
                 },
                 "horizontal_text_align"=>{
-                    if horizontal_text_align.is_some() {
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Section style/horizontal text align"),true));
-                    }
+                    self.define(&mut defined,"Section style/horizontal text align",&horizontal_text_align)?;
                     horizontal_text_align=Some(self.horizontal_text_align()?);
                 },
                 "margin"=>{
+                    self.mark_defined(&mut defined,"Page style/margin");
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
+                    self.skip_spaces();
                     if let Some(margin)=&mut margin {
                         if margin.is_individual() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin-*"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin-*"));
                         }
-                        return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                        return Err(self.already_defined(&defined,"Page style/margin"));
                     } else {
                         margin=Some(SizedSides::All(self.size()?));
                     }
                 },
                 "margin_left"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_left");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
+                    self.skip_spaces();
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_left_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_left"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_left"));
                         }
                         margin.set_left(self.size()?);
                     } else {
@@ -627,15 +1938,17 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_right"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_right");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
+                    self.skip_spaces();
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_right_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_right"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_right"));
                         }
                         margin.set_right(self.size()?);
                     } else {
@@ -648,15 +1961,17 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_top"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_top");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
+                    self.skip_spaces();
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_top_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_top"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_top"));
                         }
                         margin.set_top(self.size()?);
                     } else {
@@ -669,15 +1984,17 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                     }
                 },
                 "margin_bottom"=>{
+                    self.mark_defined(&mut defined,"Page style/margin_bottom");
+                    self.mark_defined(&mut defined,"Page style/margin-*");
                     if !self.then(":")? {
                         return Err(self.create_error(ErrorKind::ExpectedColon,true));
                     }
-                    self.skip(WHITESPACE);
+                    self.skip_spaces();
                     if let Some(margin)=&mut margin {
                         if margin.is_all() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin"));
                         } else if margin.is_bottom_defined() {
-                            return Err(self.create_error(ErrorKind::AlreadyDefined("Page style/margin_bottom"),true));
+                            return Err(self.already_defined(&defined,"Page style/margin_bottom"));
                         }
                         margin.set_bottom(self.size()?);
                     } else {
@@ -689,15 +2006,20 @@ impl<'doc> Parser<'doc> for GenericParser<'doc,ErrorKind> {
                         });
                     }
                 },
-                _=>return Err(self.create_error(ErrorKind::ExpectedPageStyle,true)),
+                _=>return Err(self.unknown_key("page style",name,PAGE_STYLE_KEYS)),
             }
         }
-        return Ok(PageStyle{page_size,text_color,background_color,margin,vertical_text_align,horizontal_text_align});
+        return Ok(PageStyle{page_size,orientation,text_color,background_color,margin,vertical_text_align,horizontal_text_align});
     }
 }
 
 
-type Result<'doc,T>=std::result::Result<T,Error<'doc,ErrorKind>>;
+/// Where `part` starts in `source`, if it is a slice of it.
+fn offset_in(source:&str,part:&str)->Option<usize> {
+    let offset=(part.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    return (offset<=source.len()).then_some(offset);
+}
+type Result<'doc,T>=std::result::Result<T,Error<'doc,ParseError<'doc>>>;
 
 
 #[derive(Debug)]
@@ -728,10 +2050,69 @@ pub enum ErrorKind {
     ExpectedSectionStyleBlockStart,
     ExpectedItem,
     ExpectedItemBlockStart,
+    ExpectedToc,
+    ExpectedTocBlockStart,
+    ExpectedPageNumber,
+    InvalidTocDepth,
     ExpectedTextAlign,
+    ExpectedOrientation,
+    ExpectedCodeHighlight,
+    ExpectedFootnotePlacement,
+    ExpectedBoolean,
+    ExpectedMarkdownOptions,
+    ExpectedMarkdownOptionsBlockStart,
     InvalidColorLength,
+    UnknownCodeTheme(String),
     AlreadyDefined(&'static str),
+    /// A key the block doesn't have, and what kind of block it is.
+    UnknownKey(&'static str,String),
     NumberParseError(String),
+    /// The label and the line of the section content it is on.
+    UndefinedLinkReference(String,usize),
+    UndefinedStyle(String),
+    StyleAlreadyDefined(String),
+    CircularStyle(String),
+    ExpectedVars,
+    ExpectedComponent,
+    ExpectedComponentName,
+    ExpectedComponentBlockStart,
+    ExpectedComponentBlockEnd,
+    ExpectedParameters,
+    ExpectedArguments,
+    ReservedComponentName(String),
+    ComponentAlreadyDefined(String),
+    UndefinedComponent(String),
+    WrongArgumentCount(String,usize,usize),
+    RecursiveComponent(String),
+    ExpectedTags,
+    ExpectedTagListStart,
+    ExpectedTagListEnd,
+    ExpectedTag,
+    ExpectedData,
+    ExpectedDataName,
+    ExpectedEquals,
+    DataAlreadyDefined(String),
+    InvalidData(String,String),
+    UndefinedData(String),
+    ExpectedFor,
+    ExpectedLoopVariable,
+    ExpectedIn,
+    ExpectedField(String),
+    UndefinedField(String),
+    ExpectedComparisonValue,
+    ExpectedForBlockStart,
+    ExpectedForBlockEnd,
+    ExpectedString,
+    UnterminatedString,
+    UnterminatedComment,
+    ExpectedInclude,
+    IncludeCycle(String),
+    ReadFile(String,String),
+    ExpectedVarsBlockStart,
+    ExpectedVariableName,
+    UndefinedVariable(String),
+    VariableAlreadyDefined(String),
+    WrongVariableKind(String,&'static str),
 }
 impl Display for ErrorKind {
     fn fmt(&self,f:&mut Formatter)->FmtResult {
@@ -761,49 +2142,213 @@ impl Display for ErrorKind {
             ExpectedSection=>write!(f,"Expected section"),
             ExpectedSectionBlockStart=>write!(f,"Expected section block start (`{{`)"),
             ExpectedSectionStyleBlockStart=>write!(f,"Expected section style block start (`{{`)"),
-            ExpectedItem=>write!(f,"Expected horizontal, vertical, or section"),
-            ExpectedItemBlockStart=>write!(f,"Expected horizontal, vertical, or section block start (`{{`)"),
+            ExpectedItem=>write!(f,"Expected horizontal, vertical, section, or toc"),
+            ExpectedItemBlockStart=>write!(f,"Expected horizontal, vertical, section, or toc block start (`{{`)"),
+            ExpectedToc=>write!(f,"Expected table of contents"),
+            ExpectedTocBlockStart=>write!(f,"Expected table of contents block start (`{{`)"),
+            ExpectedPageNumber=>write!(f,"Expected page number starting from 1"),
+            InvalidTocDepth=>write!(f,"Table of contents depth must be a heading level from 1 to 6"),
             ExpectedTextAlign=>write!(f,"Expected text align"),
+            ExpectedOrientation=>write!(f,"Expected orientation (`Portrait` or `Landscape`)"),
+            ExpectedCodeHighlight=>write!(f,"Expected code highlight mode: `Inline` or `Classes`"),
+            ExpectedFootnotePlacement=>write!(f,"Expected footnote placement: `Page` or `Document`"),
+            ExpectedBoolean=>write!(f,"Expected `true` or `false`"),
+            ExpectedMarkdownOptions=>write!(f,"Expected markdown options"),
+            ExpectedMarkdownOptionsBlockStart=>write!(f,"Expected markdown block start (`{{`)"),
             InvalidColorLength=>write!(f,"Invalid hex code length. Expected 3, 4, 6, or 8 digits."),
+            UnknownCodeTheme(theme)=>write!(f,"Unknown code theme `{}`. Expected one of: {}",theme,THEMES.join(", ")),
             AlreadyDefined(item)=>write!(f,"{} is already defined",item),
+            UnknownKey(block,key)=>write!(f,"Unknown {} key `{}`",block,key),
             NumberParseError(s)=>write!(f,"Error parsing number: {}",s),
+            UndefinedLinkReference(label,line)=>write!(f,"Undefined link reference `{}` on line {} of the section content",label,line),
+            UndefinedStyle(name)=>write!(f,"Undefined style `{}`",name),
+            StyleAlreadyDefined(name)=>write!(f,"Style `{}` is already defined",name),
+            CircularStyle(chain)=>write!(f,"Circular style inheritance: {}",chain),
+            ExpectedVars=>write!(f,"Expected vars"),
+            ExpectedComponent=>write!(f,"Expected component"),
+            ExpectedComponentName=>write!(f,"Expected component name"),
+            ExpectedComponentBlockStart=>write!(f,"Expected component block start (`{{`)"),
+            ExpectedComponentBlockEnd=>write!(f,"Expected component block end (`}}`)"),
+            ExpectedParameters=>write!(f,"Expected parameters, like `(title, body)`"),
+            ExpectedArguments=>write!(f,"Expected arguments, like `(\"Title\", \"Body\")`"),
+            ReservedComponentName(name)=>write!(f,"`{}` can't be used as a component name",name),
+            ComponentAlreadyDefined(name)=>write!(f,"Component `{}` is already defined",name),
+            UndefinedComponent(name)=>write!(f,"Undefined component `{}`",name),
+            WrongArgumentCount(name,expected,got)=>write!(f,"Component `{}` takes {} arguments but {} were given",name,expected,got),
+            RecursiveComponent(chain)=>write!(f,"Component uses itself: {}",chain),
+            ExpectedTags=>write!(f,"Expected tags"),
+            ExpectedTagListStart=>write!(f,"Expected tag list start (`[`)"),
+            ExpectedTagListEnd=>write!(f,"Expected tag list end (`]`)"),
+            ExpectedTag=>write!(f,"Expected tag name"),
+            ExpectedData=>write!(f,"Expected data"),
+            ExpectedDataName=>write!(f,"Expected data name"),
+            ExpectedEquals=>write!(f,"Expected `=`"),
+            DataAlreadyDefined(name)=>write!(f,"Data `{}` is already defined",name),
+            InvalidData(file,msg)=>write!(f,"Could not read data from `{}`: {}",file,msg),
+            UndefinedData(name)=>write!(f,"Undefined data `{}`",name),
+            ExpectedFor=>write!(f,"Expected for"),
+            ExpectedLoopVariable=>write!(f,"Expected loop variable name"),
+            ExpectedIn=>write!(f,"Expected `in`"),
+            ExpectedField(var)=>write!(f,"Expected a field, like `{}.name`",var),
+            UndefinedField(field)=>write!(f,"No record has the field `{}`",field),
+            ExpectedComparisonValue=>write!(f,"Expected a value to compare with"),
+            ExpectedForBlockStart=>write!(f,"Expected for block start (`{{`)"),
+            ExpectedForBlockEnd=>write!(f,"Expected for block end (`}}`)"),
+            ExpectedString=>write!(f,"Expected string (`\"...\"`)"),
+            UnterminatedString=>write!(f,"Unterminated string. Strings end with `\"` on the same line"),
+            UnterminatedComment=>write!(f,"Unterminated comment. Comments that start with `/*` end with `*/`"),
+            ExpectedInclude=>write!(f,"Expected include"),
+            IncludeCycle(chain)=>write!(f,"Include cycle: {}",chain),
+            ReadFile(path,e)=>write!(f,"Could not read `{}`: {}",path,e),
+            ExpectedVarsBlockStart=>write!(f,"Expected vars block start (`{{`)"),
+            ExpectedVariableName=>write!(f,"Expected variable name"),
+            UndefinedVariable(name)=>write!(f,"Undefined variable `${}`",name),
+            VariableAlreadyDefined(name)=>write!(f,"Variable `${}` is already defined",name),
+            WrongVariableKind(name,kind)=>write!(f,"Variable `${}` is not a {}",name,kind),
         }
     }
 }
-impl EOFError for ErrorKind {
-    fn create_eof()->Self {ErrorKind::UnexpectedEof}
+impl EOFError for ParseError<'_> {
+    fn create_eof()->Self {
+        ParseError::new(ErrorKind::UnexpectedEof,None,Vec::new(),None)
+    }
 }
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum Item {
     Vertical {
         items:Vec<Self>,
         style:Option<SectionStyle>,
+        tags:Vec<String>,
     },
     Horizontal {
         items:Vec<Self>,
         style:Option<SectionStyle>,
+        tags:Vec<String>,
     },
     Section(Section),
+    Toc(Toc),
 }
-#[derive(Debug)]
+impl Item {
+    pub fn style(&self)->Option<&SectionStyle> {
+        match self {
+            Item::Vertical{style,..}|Item::Horizontal{style,..}=>style.as_ref(),
+            Item::Section(section)=>section.style.as_ref(),
+            Item::Toc(toc)=>toc.style.as_ref(),
+        }
+    }
+    pub fn tags(&self)->&[String] {
+        match self {
+            Item::Vertical{tags,..}|Item::Horizontal{tags,..}=>tags,
+            Item::Section(section)=>&section.tags,
+            Item::Toc(toc)=>&toc.tags,
+        }
+    }
+    /// Removes the items in this one that aren't for `profile`.
+    fn retain_profile(&mut self,profile:&str) {
+        if let Item::Vertical{items,..}|Item::Horizontal{items,..}=self {
+            items.retain(|item|item.is_for_profile(profile));
+            for item in items.iter_mut() {
+                item.retain_profile(profile);
+            }
+        }
+    }
+    /// Items without tags are in every profile.
+    fn is_for_profile(&self,profile:&str)->bool {
+        let tags=self.tags();
+        tags.is_empty()||tags.iter().any(|tag|tag==profile)
+    }
+}
+#[derive(Debug,Clone,PartialEq)]
 pub enum Size {
     Inches(f32),
     Points(f32),
     Pixels(f32),
+    Millimeters(f32),
     Percent(f32),
 }
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum PageSize {
     PortraitLetter,
     LandscapeLetter,
     /// Has no defined size. Probably counts as responsive design.
     Webpage,
+    /// A standard paper, envelope, or card size. It is portrait unless the page style's
+    /// `orientation` says otherwise.
+    Paper(Paper),
     Custom {
         width:Size,
         height:Size,
     },
 }
-#[derive(Debug)]
+impl PageSize {
+    /// The width and height, or `None` for a webpage. `orientation` overrides the orientation of
+    /// `PortraitLetter` and `LandscapeLetter` too, but custom sizes are used as written.
+    pub fn dimensions(&self,orientation:Option<Orientation>)->Option<(Size,Size)> {
+        use PageSize::*;
+        let (width,height,default_orientation)=match self {
+            PortraitLetter=>(Size::Inches(8.5),Size::Inches(11.0),Orientation::Portrait),
+            LandscapeLetter=>(Size::Inches(8.5),Size::Inches(11.0),Orientation::Landscape),
+            Webpage=>return None,
+            Paper(paper)=>{
+                let (width,height)=paper.dimensions();
+                (width,height,Orientation::Portrait)
+            },
+            Custom{width,height}=>return Some((width.clone(),height.clone())),
+        };
+        match orientation.unwrap_or(default_orientation) {
+            Orientation::Portrait=>Some((width,height)),
+            Orientation::Landscape=>Some((height,width)),
+        }
+    }
+}
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum Paper {
+    Letter,
+    Legal,
+    Tabloid,
+    A3,
+    A4,
+    A5,
+    A6,
+    B5,
+    EnvelopeDL,
+    EnvelopeC5,
+    EnvelopeC6,
+    /// A US #10 business envelope.
+    Envelope10,
+    /// A 3x5 inch index card.
+    IndexCard,
+    BusinessCard,
+}
+impl Paper {
+    /// The portrait width and height.
+    pub fn dimensions(&self)->(Size,Size) {
+        use Paper::*;
+        use Size::{Inches,Millimeters};
+        match self {
+            Letter=>(Inches(8.5),Inches(11.0)),
+            Legal=>(Inches(8.5),Inches(14.0)),
+            Tabloid=>(Inches(11.0),Inches(17.0)),
+            A3=>(Millimeters(297.0),Millimeters(420.0)),
+            A4=>(Millimeters(210.0),Millimeters(297.0)),
+            A5=>(Millimeters(148.0),Millimeters(210.0)),
+            A6=>(Millimeters(105.0),Millimeters(148.0)),
+            B5=>(Millimeters(176.0),Millimeters(250.0)),
+            EnvelopeDL=>(Millimeters(110.0),Millimeters(220.0)),
+            EnvelopeC5=>(Millimeters(162.0),Millimeters(229.0)),
+            EnvelopeC6=>(Millimeters(114.0),Millimeters(162.0)),
+            Envelope10=>(Inches(4.125),Inches(9.5)),
+            IndexCard=>(Inches(3.0),Inches(5.0)),
+            BusinessCard=>(Inches(2.0),Inches(3.5)),
+        }
+    }
+}
+#[derive(Debug,PartialEq,Copy,Clone)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+#[derive(Debug,Clone,PartialEq)]
 pub enum SizedSides {
     All(Size),
     Individual {
@@ -814,6 +2359,23 @@ pub enum SizedSides {
     },
 }
 impl SizedSides {
+    /// Fills in the sides this leaves out from `base`.
+    pub fn inherit(&self,base:&SizedSides)->SizedSides {
+        use SizedSides::*;
+        let (base_left,base_right,base_top,base_bottom)=match base {
+            All(size)=>(Some(size),Some(size),Some(size),Some(size)),
+            Individual{left,right,top,bottom}=>(left.as_ref(),right.as_ref(),top.as_ref(),bottom.as_ref()),
+        };
+        match self {
+            All(_)=>self.clone(),
+            Individual{left,right,top,bottom}=>Individual {
+                left:left.clone().or_else(||base_left.cloned()),
+                right:right.clone().or_else(||base_right.cloned()),
+                top:top.clone().or_else(||base_top.cloned()),
+                bottom:bottom.clone().or_else(||base_bottom.cloned()),
+            },
+        }
+    }
     pub fn is_all(&self)->bool {
         match self {
             Self::All(_)=>true,
@@ -875,7 +2437,7 @@ impl SizedSides {
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum Direction {
     Left,
     Right,
@@ -894,38 +2456,158 @@ pub enum VTextAlign {
     Bottom,
     Center,
 }
+/// Where footnotes are collected.
+#[derive(Debug,PartialEq,Copy,Clone,Default)]
+pub enum FootnotePlacement {
+    /// At the bottom of the first page where the footnote is both referenced and defined.
+    #[default]
+    Page,
+    /// After the last page.
+    Document,
+}
+/// How highlighted code is styled in the HTML output.
+#[derive(Debug,PartialEq,Copy,Clone,Default)]
+pub enum CodeHighlight {
+    /// Every token gets a `style` attribute.
+    #[default]
+    Inline,
+    /// Tokens get classes, and the theme is emitted once as a stylesheet.
+    Classes,
+}
 
 
-#[derive(Debug)]
+#[derive(Debug,Clone,PartialEq)]
 pub struct Document {
     pub metadata:Metadata,
     pub pages:Vec<Page>,
 }
-#[derive(Debug)]
+impl Document {
+    /// A copy without the items that are tagged, but not with `profile`.
+    pub fn for_profile(&self,profile:&str)->Document {
+        let mut document=self.clone();
+        for page in document.pages.iter_mut() {
+            page.items.retain(|item|item.is_for_profile(profile));
+            for item in page.items.iter_mut() {
+                item.retain_profile(profile);
+            }
+        }
+        return document;
+    }
+    /// Whether any item is tagged with `tag`.
+    pub fn has_tag(&self,tag:&str)->bool {
+        fn item_has_tag(item:&Item,tag:&str)->bool {
+            if item.tags().iter().any(|t|t==tag) {
+                return true;
+            }
+            match item {
+                Item::Vertical{items,..}|Item::Horizontal{items,..}=>items.iter().any(|item|item_has_tag(item,tag)),
+                _=>false,
+            }
+        }
+        self.pages.iter().any(|page|page.items.iter().any(|item|item_has_tag(item,tag)))
+    }
+}
+#[derive(Debug,Clone,Default,PartialEq)]
 pub struct Metadata {
     pub title:String,
     pub page_style:Option<PageStyle>,
+    pub code_theme:Option<String>,
+    pub code_highlight:Option<CodeHighlight>,
+    pub markdown:Option<MarkdownOptions>,
+    pub footnotes:Option<FootnotePlacement>,
+    /// The variables from the `vars` block, in the order they are defined.
+    pub vars:Vec<(String,Var)>,
 }
-#[derive(Debug)]
+/// A value from the metadata's `vars` block.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Var {
+    Color(Color),
+    Size(Size),
+    Text(String),
+}
+impl Var {
+    pub fn kind(&self)->&'static str {
+        match self {
+            Var::Color(_)=>"color",
+            Var::Size(_)=>"size",
+            Var::Text(_)=>"text",
+        }
+    }
+    /// The value as it would be written in the document.
+    pub fn text(&self)->String {
+        match self {
+            Var::Color(Color{r,g,b,a:Some(a)})=>format!("#{:02x}{:02x}{:02x}{:02x}",r,g,b,a),
+            Var::Color(Color{r,g,b,a:None})=>format!("#{:02x}{:02x}{:02x}",r,g,b),
+            Var::Size(size)=>{
+                use Size::*;
+                match size {
+                    Inches(c)=>format!("{}in",c),
+                    Points(c)=>format!("{}pt",c),
+                    Pixels(c)=>format!("{}px",c),
+                    Millimeters(c)=>format!("{}mm",c),
+                    Percent(c)=>format!("{}%",c),
+                }
+            },
+            Var::Text(text)=>text.clone(),
+        }
+    }
+}
+#[derive(Debug,Clone,PartialEq)]
 pub struct Page {
     pub items:Vec<Item>,
     pub style:Option<PageStyle>,
 }
-#[derive(Debug,Default)]
+#[derive(Debug,Default,Clone,PartialEq)]
 pub struct PageStyle {
     pub page_size:Option<PageSize>,
+    pub orientation:Option<Orientation>,
     pub text_color:Option<Color>,
     pub background_color:Option<Color>,
     pub margin:Option<SizedSides>,
     pub horizontal_text_align:Option<HTextAlign>,
     pub vertical_text_align:Option<VTextAlign>,
 }
-#[derive(Debug)]
+impl PageStyle {
+    /// The page's width and height, using `default` for the size or orientation when this style
+    /// leaves them out. An orientation without a size means letter paper. `None` for a webpage or
+    /// when neither is given.
+    pub fn dimensions(&self,default:Option<&PageStyle>)->Option<(Size,Size)> {
+        let orientation=self.orientation.or(default.and_then(|d|d.orientation));
+        let page_size=self.page_size.as_ref().or(default.and_then(|d|d.page_size.as_ref()));
+        match (page_size,orientation) {
+            (Some(page_size),_)=>page_size.dimensions(orientation),
+            (None,Some(_))=>PageSize::Paper(Paper::Letter).dimensions(orientation),
+            (None,None)=>None,
+        }
+    }
+}
+#[derive(Debug,Clone)]
 pub struct Section {
     pub style:Option<SectionStyle>,
     pub content:Vec<String>,
+    /// The profiles the section is in. Sections without tags are in every profile.
+    pub tags:Vec<String>,
+    /// Where the content is defined, for warnings about it.
+    pub location:Option<Location>,
+}
+/// Sections are the same when they have the same content, wherever it came from.
+impl PartialEq for Section {
+    fn eq(&self,other:&Self)->bool {
+        self.style==other.style&&self.content==other.content&&self.tags==other.tags
+    }
+}
+/// A generated outline of the document's headings.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Toc {
+    /// The deepest heading level to include. Defaults to 3.
+    pub depth:Option<u8>,
+    /// Page numbers starting from 1. Defaults to every page.
+    pub pages:Option<Vec<usize>>,
+    pub style:Option<SectionStyle>,
+    /// The profiles the table of contents is in. Without tags it is in every profile.
+    pub tags:Vec<String>,
 }
-#[derive(Debug,Default)]
+#[derive(Debug,Default,Clone,PartialEq)]
 pub struct SectionStyle {
     pub width:Option<Size>,
     pub height:Option<Size>,
@@ -937,8 +2619,65 @@ pub struct SectionStyle {
     pub margin:Option<SizedSides>,
     pub horizontal_text_align:Option<HTextAlign>,
     pub vertical_text_align:Option<VTextAlign>,
+    pub markdown:Option<MarkdownOptions>,
 }
-#[derive(Debug)]
+impl SectionStyle {
+    /// Returns this style with anything it leaves out taken from `base`.
+    pub fn inherit(&self,base:&SectionStyle)->SectionStyle {
+        let margin=match (&self.margin,&base.margin) {
+            (Some(margin),Some(base))=>Some(margin.inherit(base)),
+            (margin,base)=>margin.clone().or_else(||base.clone()),
+        };
+        let markdown=match (&self.markdown,&base.markdown) {
+            (Some(markdown),Some(base))=>Some(base.merge(Some(markdown))),
+            (markdown,base)=>markdown.clone().or_else(||base.clone()),
+        };
+        return SectionStyle {
+            width:self.width.clone().or_else(||base.width.clone()),
+            height:self.height.clone().or_else(||base.height.clone()),
+            align:self.align.clone().or_else(||base.align.clone()),
+            font:self.font.clone().or_else(||base.font.clone()),
+            font_size:self.font_size.clone().or_else(||base.font_size.clone()),
+            text_color:self.text_color.clone().or_else(||base.text_color.clone()),
+            background_color:self.background_color.clone().or_else(||base.background_color.clone()),
+            margin,
+            horizontal_text_align:self.horizontal_text_align.or(base.horizontal_text_align),
+            vertical_text_align:self.vertical_text_align.or(base.vertical_text_align),
+            markdown,
+        };
+    }
+}
+/// Optional markdown extensions. Strikethrough and footnotes are always enabled.
+#[derive(Debug,Default,Clone,PartialEq)]
+pub struct MarkdownOptions {
+    pub tables:Option<bool>,
+    pub tasklists:Option<bool>,
+    pub smart_punctuation:Option<bool>,
+    pub heading_attributes:Option<bool>,
+}
+impl MarkdownOptions {
+    /// Returns these options with any that `other` defines replaced.
+    pub fn merge(&self,other:Option<&Self>)->Self {
+        let Some(other)=other else {
+            return self.clone();
+        };
+        return MarkdownOptions {
+            tables:other.tables.or(self.tables),
+            tasklists:other.tasklists.or(self.tasklists),
+            smart_punctuation:other.smart_punctuation.or(self.smart_punctuation),
+            heading_attributes:other.heading_attributes.or(self.heading_attributes),
+        };
+    }
+    pub fn options(&self)->Options {
+        let mut options=Options::ENABLE_STRIKETHROUGH|Options::ENABLE_FOOTNOTES;
+        options.set(Options::ENABLE_TABLES,self.tables.unwrap_or(false));
+        options.set(Options::ENABLE_TASKLISTS,self.tasklists.unwrap_or(false));
+        options.set(Options::ENABLE_SMART_PUNCTUATION,self.smart_punctuation.unwrap_or(false));
+        options.set(Options::ENABLE_HEADING_ATTRIBUTES,self.heading_attributes.unwrap_or(false));
+        return options;
+    }
+}
+#[derive(Debug,Clone,PartialEq)]
 pub struct Color {
     pub r:u8,
     pub g:u8,
@@ -947,6 +2686,63 @@ pub struct Color {
 }
 
 
+/// Replaces each `$NAME` in `body` that `lookup` knows with its value. Names can have `.`s in
+/// them when `dotted` is set. Other `$` references are left for the parser. A value in a text value,
+/// like a `title`, gets its `$`s doubled, so they aren't read as variables again.
+fn substitute(body:&str,dotted:bool,mut lookup:impl FnMut(&str)->Option<String>)->String {
+    let name_char=|c:char|c.is_ascii_alphanumeric()||c=='_';
+    let text_values=SyntaxTree::new(body).text_values();
+    let mut out=String::new();
+    let mut rest=body;
+    while let Some(i)=rest.find('$') {
+        out.push_str(&rest[..i+1]);
+        rest=&rest[i+1..];
+        if rest.starts_with('$') {
+            out.push('$');
+            rest=&rest[1..];
+            continue;
+        }
+        let mut len=rest.find(|c:char|!name_char(c)).unwrap_or(rest.len());
+        // A `.` only continues the name when a name follows it, so a sentence can end with one.
+        while dotted&&rest[len..].starts_with('.')&&rest[len+1..].starts_with(name_char) {
+            len+=1;
+            len+=rest[len..].find(|c:char|!name_char(c)).unwrap_or(rest.len()-len);
+        }
+        if let Some(value)=lookup(&rest[..len]) {
+            let at=body.len()-rest.len()-1;
+            let value=if text_values.iter().any(|values|values.contains(&at)) {value.replace('$',"$$")} else {value};
+            out.pop();
+            // Keep every line of the value at the indentation of the line it is on, which
+            // content blocks need.
+            let indent=out.rsplit('\n').next().unwrap_or("");
+            let indent=&indent[..indent.len()-indent.trim_start().len()];
+            out.push_str(&value.replace('\n',&format!("\n{}",indent)));
+            rest=&rest[len..];
+        }
+    }
+    out.push_str(rest);
+    return out;
+}
+/// Finds the first full (`[text][label]`) or collapsed (`[label][]`) reference link whose label has
+/// no definition in the section. Shortcut links are ignored, since `[text]` is often just text in
+/// brackets.
+fn undefined_link_reference(lines:&[String],options:Options)->Option<(String,usize)> {
+    let source=lines.join("\n");
+    let mut undefined=None;
+    {
+        let mut callback=|link:BrokenLink|{
+            if undefined.is_none()&&link.link_type!=LinkType::Shortcut {
+                let line=source[..link.span.start].matches('\n').count()+1;
+                undefined=Some((link.reference.to_string(),line));
+            }
+            None
+        };
+        MarkdownParser::new_with_broken_link_callback(&source,options,Some(&mut callback)).for_each(drop);
+    }
+    return undefined;
+}
+
+
 const WHITESPACE:&[&str]=&[
     " ",
 ];
@@ -954,6 +2750,200 @@ const EXT_WHITESPACE:&[&str]=&[
     " ",
     "\r","\n",
 ];
+/// The most errors collected from one document. Past this, they are mostly caused by earlier ones.
+pub const MAX_ERRORS:usize=20;
+/// Words that start something else where an item or page is expected, so they can't name a
+/// component.
+const KEYWORDS:&[&str]=&[
+    "vertical","horizontal","section","toc","style","include","component","for","page","metadata",
+];
+const TAG_CHARS:&[&str]=&[
+    "a","b","c","d","e","f","g","h","i","j","k","l","m","n","o","p","q","r","s","t","u","v","w","x","y","z",
+    "A","B","C","D","E","F","G","H","I","J","K","L","M","N","O","P","Q","R","S","T","U","V","W","X","Y","Z",
+    "0","1","2","3","4","5","6","7","8","9","_","-",
+];
+/// The ways a `where` can compare a field, with longer ones first so `<=` isn't read as `<`.
+const COMPARISONS:&[&str]=&["==","!=","<=",">=","<",">"];
+/// A path to a field, like `links.home`.
+const FIELD_PATH_CHARS:&[&str]=&[
+    "a","b","c","d","e","f","g","h","i","j","k","l","m","n","o","p","q","r","s","t","u","v","w","x","y","z",
+    "A","B","C","D","E","F","G","H","I","J","K","L","M","N","O","P","Q","R","S","T","U","V","W","X","Y","Z",
+    "0","1","2","3","4","5","6","7","8","9","_",".",
+];
+/// A value compared against without quotes, like `2020` or `true`.
+const FIELD_VALUE_CHARS:&[&str]=&[
+    "a","b","c","d","e","f","g","h","i","j","k","l","m","n","o","p","q","r","s","t","u","v","w","x","y","z",
+    "A","B","C","D","E","F","G","H","I","J","K","L","M","N","O","P","Q","R","S","T","U","V","W","X","Y","Z",
+    "0","1","2","3","4","5","6","7","8","9","_",".","-",
+];
+const VAR_NAME_CHARS:&[&str]=&[
+    "a","b","c","d","e","f","g","h","i","j","k","l","m","n","o","p","q","r","s","t","u","v","w","x","y","z",
+    "A","B","C","D","E","F","G","H","I","J","K","L","M","N","O","P","Q","R","S","T","U","V","W","X","Y","Z",
+    "1","2","3","4","5","6","7","8","9","0","_",
+];
 const NEWLINE:&[&str]=&[
     "\n","\r\n",
 ];
+/// Where a value that takes the rest of the line ends. Comments after one need a space before
+/// them, so values like URLs can hold `//`.
+const TEXT_END:&[&str]=&[
+    "\n","\r\n"," //"," /*",
+];
+/// The keys of each kind of block, to suggest one when a key is misspelled.
+pub const SECTION_KEYS:&[&str]=&["style","tags","content","content_file"];
+pub const SECTION_STYLE_KEYS:&[&str]=&[
+    "use","extends","markdown","width","height","vertical_text_align","horizontal_text_align","align","font",
+    "font_size","text_color","background_color","margin","margin_left","margin_right","margin_top","margin_bottom",
+];
+pub const PAGE_STYLE_KEYS:&[&str]=&[
+    "page_size","orientation","text_color","background_color","vertical_text_align","horizontal_text_align",
+    "margin","margin_left","margin_right","margin_top","margin_bottom",
+];
+pub const PAGE_SIZE_KEYS:&[&str]=&["width","height"];
+pub const MARKDOWN_KEYS:&[&str]=&["tables","tasklists","smart_punctuation","heading_attributes"];
+pub const METADATA_KEYS:&[&str]=&["title","code_theme","code_highlight","footnotes","style","markdown","data","vars"];
+pub const TOC_KEYS:&[&str]=&["style","depth","pages","tags"];
+/// The keys of `vertical` and `horizontal` items, besides the items in them.
+pub const ITEM_KEYS:&[&str]=&["style","tags"];
+/// What can start an item, besides a component.
+pub const ITEM_KINDS:&[&str]=&["vertical","horizontal","section","toc","for","include"];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The document in `source`, and the messages of its errors in order.
+    fn parse(source:&str)->(Document,Vec<String>) {
+        let sources=Sources::default();
+        let (doc,errors,..)=DocumentParser::new(source,"",&sources).into_document();
+        return (doc,errors.iter().map(|e|e.kind.to_string()).collect());
+    }
+    fn errors(source:&str)->Vec<String> {
+        return parse(source).1;
+    }
+    /// Every section in the document, in order.
+    fn sections(doc:&Document)->Vec<&Section> {
+        fn visit<'a>(items:&'a [Item],sections:&mut Vec<&'a Section>) {
+            for item in items {
+                match item {
+                    Item::Vertical{items,..}|Item::Horizontal{items,..}=>visit(items,sections),
+                    Item::Section(section)=>sections.push(section),
+                    Item::Toc(_)=>{},
+                }
+            }
+        }
+        let mut sections=Vec::new();
+        for page in doc.pages.iter() {
+            visit(&page.items,&mut sections);
+        }
+        return sections;
+    }
+
+    #[test]
+    fn reports_every_bad_key_in_a_block() {
+        let source="metadata {\n    title: T\n    markdown {\n        tabels: true\n        tasklists: maybe\n        tables: true\n    }\n}\npage {\n    toc {\n        dept: 2\n        depth: 9\n        pages: 1\n    }\n}\n";
+        assert_eq!(errors(source),[
+            "Unknown markdown key `tabels`",
+            "Expected `true` or `false`",
+            "Unknown table of contents key `dept`",
+            "Table of contents depth must be a heading level from 1 to 6",
+        ]);
+    }
+
+    #[test]
+    fn says_when_errors_were_left_out() {
+        let truncated=|count:usize|{
+            let keys=(0..count).map(|i|format!("    key{}: x\n",i)).collect::<String>();
+            let source=format!("metadata {{\n    title: T\n{}}}\n",keys);
+            let sources=Sources::default();
+            let (_,errors,_,truncated)=DocumentParser::new(&source,"",&sources).into_document();
+            (errors.len(),truncated)
+        };
+        assert_eq!(truncated(MAX_ERRORS),(MAX_ERRORS,false));
+        assert_eq!(truncated(MAX_ERRORS+1),(MAX_ERRORS,true));
+    }
+
+    #[test]
+    fn replaces_variables_in_text() {
+        let (doc,found)=parse("metadata {\n    vars {\n        who: Ann\n    }\n    title: $who paid $5, $ and $$who$\n}\n");
+        assert!(found.is_empty());
+        assert_eq!(doc.metadata.title,"Ann paid $5, $ and $who$");
+        assert_eq!(errors("metadata {\n    title: $who\n    vars {\n        who: Ann\n    }\n}\n"),["Undefined variable `$who`"]);
+    }
+
+    #[test]
+    fn reports_a_missing_title_once() {
+        assert_eq!(errors("metadata {\n    title: $who\n}\n"),["Undefined variable `$who`"]);
+        assert_eq!(errors("metadata {\n}\n"),["Expected title"]);
+    }
+
+    #[test]
+    fn expands_components_with_their_arguments_as_written() {
+        let source="metadata {\n    title: T\n}\ncomponent job(name) {\n    section {\n        style {\n            font: $name\n        }\n        content {\n            # $name\n        }\n    }\n}\npage {\n    job(\"Price $5 for $$who\")\n    job(\"Plain\")\n}\n";
+        let (doc,errors)=parse(source);
+        assert!(errors.is_empty(),"{:?}",errors);
+        let sections=sections(&doc);
+        assert_eq!(sections.len(),2);
+        assert_eq!(sections[0].style.as_ref().unwrap().font.as_deref(),Some("Price $5 for $$who"));
+        assert_eq!(sections[0].content,["# Price $5 for $$who"]);
+        assert_eq!(sections[1].content,["# Plain"]);
+    }
+
+    #[test]
+    fn points_errors_in_components_at_their_use() {
+        let source="metadata {\n    title: T\n}\ncomponent card() {\n    section {\n        conten {\n        }\n    }\n}\ncomponent loop() {\n    loop()\n}\npage {\n    card()\n    loop()\n}\n";
+        let sources=Sources::default();
+        let (_,errors,..)=DocumentParser::new(source,"",&sources).into_document();
+        let messages=errors.iter().map(|e|e.kind.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages,["Unknown section key `conten`","Component uses itself: loop -> loop"]);
+        let label=&errors[0].labels[0];
+        assert_eq!(label.message,"expanded from here");
+        assert_eq!(label.offset,source.rfind("card()").unwrap());
+    }
+
+    #[test]
+    fn includes_files_and_catches_cycles() {
+        let dir=std::env::temp_dir().join(format!("docbuild-includes-{}",std::process::id()));
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        let write=|name:&str,contents:&str|std::fs::write(dir.join(name),contents).unwrap();
+        write("main.docbuild","metadata {\n    vars {\n        accent: #aaffff\n    }\n    title: T\n}\ncomponent card(text) {\n    section {\n        content {\n            $text\n        }\n    }\n}\npage {\n    vertical {\n        include \"parts/items.docbuild\"\n    }\n}\ninclude \"parts/loop.docbuild\"\n");
+        write("parts/items.docbuild","section {\n    style {\n        text_color: $accent\n    }\n    content {\n        Included\n    }\n}\ncard(\"From a component\")\n");
+        write("parts/loop.docbuild","include \"loop.docbuild\"\n");
+        let sources=Sources::default();
+        let (file,source)=sources.load(&dir.join("main.docbuild")).unwrap();
+        let (doc,errors,..)=DocumentParser::new(source,file,&sources).into_document();
+        let looped=canonicalize(dir.join("parts/loop.docbuild")).unwrap().display().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let messages=errors.iter().map(|e|e.kind.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages,[format!("Include cycle: {} -> {}",looped,looped)]);
+        let sections=sections(&doc);
+        assert_eq!(sections.len(),2);
+        assert_eq!(sections[0].content,["Included"]);
+        assert_eq!(sections[0].style.as_ref().unwrap().text_color,Some(Color{r:0xaa,g:0xff,b:0xff,a:None}));
+        assert_eq!(sections[1].content,["From a component"]);
+    }
+
+    #[test]
+    fn repeats_for_loops_for_the_records_they_keep() {
+        let dir=std::env::temp_dir().join(format!("docbuild-for-{}",std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("jobs.json"),r#"[
+            {"name":"Old","year":2018,"public":true},
+            {"name":"Paid $5","year":2021,"public":true},
+            {"name":"Hidden","year":2022,"public":false},
+            {"name":"Middle","year":2020,"public":true}
+        ]"#).unwrap();
+        let path=dir.join("main.docbuild");
+        std::fs::write(&path,"metadata {\n    title: T\n    data jobs = \"jobs.json\"\n}\npage {\n    for job in jobs where job.public where job.year >= 2019 sort job.year desc {\n        section {\n            style {\n                font: $job.name\n            }\n            content {\n                $job.name in $job.year\n            }\n        }\n    }\n}\n").unwrap();
+        let sources=Sources::default();
+        let (file,source)=sources.load(&path).unwrap();
+        let (doc,errors,..)=DocumentParser::new(source,file,&sources).into_document();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(errors.is_empty(),"{:?}",errors.iter().map(|e|e.kind.to_string()).collect::<Vec<_>>());
+        let sections=sections(&doc);
+        let content=sections.iter().map(|s|s.content.join("\n")).collect::<Vec<_>>();
+        assert_eq!(content,["Paid $5 in 2021","Middle in 2020"]);
+        assert_eq!(sections[0].style.as_ref().unwrap().font.as_deref(),Some("Paid $5"));
+    }
+}
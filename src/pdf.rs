@@ -0,0 +1,901 @@
+use pulldown_cmark::{
+    Parser,
+    Event,
+    Tag,
+    Options,
+    HeadingLevel,
+};
+use crate::parser::{
+    Document,
+    Page,
+    PageStyle,
+    Section,
+    SectionStyle,
+    Item,
+    Toc,
+    MarkdownOptions,
+    FootnotePlacement,
+    Size,
+    Color,
+    SizedSides,
+    VTextAlign,
+    HTextAlign,
+};
+use crate::render::{
+    Renderer,
+    ParentDirection,
+};
+use crate::outline::{
+    Outline,
+    Footnotes,
+    Footnote,
+};
+use crate::warning::{
+    Warning,
+    WarningKind,
+};
+use std::io::{
+    Write,
+    Result as IoResult,
+};
+
+
+const DEFAULT_FONT_SIZE:f32=12.0;
+const LINE_HEIGHT:f32=1.2;
+const LIST_INDENT:f32=18.0;
+/// Advance widths of the printable ASCII range for Helvetica, in thousandths of an em.
+const HELVETICA_WIDTHS:[u16;95]=[
+    278,278,355,556,556,889,667,191,333,333,389,584,278,333,278,278,
+    556,556,556,556,556,556,556,556,556,556,278,278,584,584,584,556,
+    1015,667,667,722,722,667,611,778,722,278,500,667,556,833,722,778,
+    667,778,722,667,611,722,667,944,667,667,611,278,278,278,469,556,
+    333,556,556,500,556,556,278,556,556,222,222,500,222,833,556,556,
+    556,556,333,500,278,556,500,722,500,500,500,334,260,334,584,
+];
+
+
+/// Renders documents into a PDF using the standard Type 1 fonts, so no font files are needed.
+///
+/// Items are laid out like the HTML output's flexboxes: when a container starts, its children's
+/// boxes are computed up front and handed out as the children are visited.
+#[derive(Default)]
+pub struct PdfRenderer {
+    writer:PdfWriter,
+    catalog_id:usize,
+    pages_id:usize,
+    font_resources:String,
+    default_style:PageStyle,
+    markdown:MarkdownOptions,
+    outline:Outline,
+    page_ids:Vec<usize>,
+    canvas:Option<Canvas>,
+    page_width:f32,
+    slots:Vec<std::vec::IntoIter<Slot>>,
+    footnotes:Footnotes,
+    footnote_placement:FootnotePlacement,
+    /// Where the current page's footnotes go, and the page's text style.
+    footnote_area:Option<(Rect,TextStyle)>,
+    pages_left:usize,
+    warnings:Vec<Warning>,
+}
+impl PdfRenderer {
+    fn next_slot(&mut self)->Slot {
+        self.slots.last_mut()
+            .and_then(|slots|slots.next())
+            .expect("Every visited item has a slot")
+    }
+    fn canvas(&mut self)->&mut Canvas {
+        self.canvas.as_mut().expect("Items are only visited inside of a page")
+    }
+}
+impl Renderer for PdfRenderer {
+    fn begin_document(&mut self,document:&Document,_:&mut dyn Write)->IoResult<()> {
+        self.writer=PdfWriter::default();
+        self.page_ids.clear();
+        self.warnings.clear();
+        self.catalog_id=self.writer.reserve();
+        self.pages_id=self.writer.reserve();
+        let fonts=[
+            self.writer.add(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec()),
+            self.writer.add(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_vec()),
+            self.writer.add(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Oblique /Encoding /WinAnsiEncoding >>".to_vec()),
+            self.writer.add(b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-BoldOblique /Encoding /WinAnsiEncoding >>".to_vec()),
+            self.writer.add(b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>".to_vec()),
+        ];
+        self.font_resources=fonts.iter()
+            .enumerate()
+            .map(|(i,id)|format!("/F{} {} 0 R",i+1,id))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.default_style=document.metadata.page_style.clone().unwrap_or_default();
+        self.markdown=document.metadata.markdown.clone().unwrap_or_default();
+        self.outline=Outline::new(document);
+        self.footnotes=Footnotes::default();
+        self.footnote_placement=document.metadata.footnotes.unwrap_or_default();
+        self.pages_left=document.pages.len();
+        return Ok(());
+    }
+    fn end_document(&mut self,document:&Document,out:&mut dyn Write)->IoResult<()> {
+        let kids=self.page_ids.iter()
+            .map(|id|format!("{} 0 R",id))
+            .collect::<Vec<_>>();
+        self.writer.set(self.pages_id,format!("<< /Type /Pages /Kids [{}] /Count {} >>",kids.join(" "),kids.len()).into_bytes());
+        self.writer.set(self.catalog_id,format!("<< /Type /Catalog /Pages {} 0 R >>",self.pages_id).into_bytes());
+        let mut info=b"<< /Title ".to_vec();
+        info.extend(pdf_string(&document.metadata.title));
+        info.extend(b" /Producer (document_builder) >>");
+        let info=self.writer.add(info);
+        let writer=std::mem::take(&mut self.writer);
+        return out.write_all(&writer.finish(self.catalog_id,info));
+    }
+    fn begin_page(&mut self,page:&Page,_:&mut dyn Write)->IoResult<()> {
+        let default_style=&self.default_style;
+        let style=page.style.clone().unwrap_or_default();
+        // Webpages have no size, so they are printed on letter paper.
+        let (width,height)=match style.dimensions(Some(default_style)) {
+            Some((width,height))=>(width.to_points(612.0),height.to_points(792.0)),
+            None=>(612.0,792.0),
+        };
+        let mut canvas=Canvas{height,out:Vec::new()};
+        let page_rect=Rect{x:0.0,y:0.0,w:width,h:height};
+        if let Some(color)=style.background_color.as_ref().or(default_style.background_color.as_ref()) {
+            canvas.fill_rect(page_rect,color);
+        }
+        let margin=style.margin.as_ref().or(default_style.margin.as_ref());
+        let content_rect=page_rect.inset(margin);
+        let text=TextStyle {
+            font_size:DEFAULT_FONT_SIZE,
+            color:style.text_color.as_ref().or(default_style.text_color.as_ref()).map(Color::to_rgb).unwrap_or((0.0,0.0,0.0)),
+            monospace:false,
+            vertical_align:style.vertical_text_align.or(default_style.vertical_text_align),
+            horizontal_align:style.horizontal_text_align.or(default_style.horizontal_text_align),
+        };
+        self.canvas=Some(canvas);
+        self.page_width=width;
+        self.footnote_area=Some((content_rect,text.clone()));
+        // `.page` is a row flexbox in the HTML output, so lay the items out the same way.
+        self.slots.push(layout_items(&page.items,content_rect,&text,true).into_iter());
+        return Ok(());
+    }
+    fn end_page(&mut self,_:&Page,_:&mut dyn Write)->IoResult<()> {
+        self.slots.pop();
+        let mut canvas=self.canvas.take().expect("Pages are not nested");
+        self.pages_left-=1;
+        if self.footnote_placement==FootnotePlacement::Page||self.pages_left==0 {
+            let footnotes=self.footnotes.take();
+            if let Some((rect,text))=self.footnote_area.take() {
+                draw_footnotes(footnotes,rect,&mut canvas,&text);
+            }
+        }
+        let content_id=self.writer.add_stream(canvas.out);
+        let page_id=self.writer.add(format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] /Resources << /Font << {} >> >> /Contents {} 0 R >>",
+            self.pages_id,
+            number(self.page_width),
+            number(canvas.height),
+            self.font_resources,
+            content_id,
+        ).into_bytes());
+        self.page_ids.push(page_id);
+        return Ok(());
+    }
+    fn begin_container(&mut self,direction:ParentDirection,items:&[Item],style:Option<&SectionStyle>,_:ParentDirection,_:&mut dyn Write)->IoResult<()> {
+        let slot=self.next_slot();
+        let text=slot.text.inherit(style);
+        if let Some(color)=style.and_then(|s|s.background_color.as_ref()) {
+            self.canvas().fill_rect(slot.rect,color);
+        }
+        let rect=slot.rect.inset(style.and_then(|s|s.margin.as_ref()));
+        self.slots.push(layout_items(items,rect,&text,direction==ParentDirection::Horizontal).into_iter());
+        return Ok(());
+    }
+    fn end_container(&mut self,_:ParentDirection,_:&mut dyn Write)->IoResult<()> {
+        self.slots.pop();
+        return Ok(());
+    }
+    fn section(&mut self,section:&Section,_:ParentDirection,_:&mut dyn Write)->IoResult<()> {
+        let slot=self.next_slot();
+        let style=section.style.as_ref();
+        let text=slot.text.inherit(style);
+        if let Some(color)=style.and_then(|s|s.background_color.as_ref()) {
+            self.canvas().fill_rect(slot.rect,color);
+        }
+        let rect=slot.rect.inset(style.and_then(|s|s.margin.as_ref()));
+        let options=self.markdown.merge(style.and_then(|s|s.markdown.as_ref())).options();
+        let canvas=self.canvas.as_mut().expect("Items are only visited inside of a page");
+        let images=section.draw(rect,canvas,&text,options,&mut self.footnotes);
+        for image in images {
+            self.warnings.push(Warning{kind:WarningKind::UnsupportedImage(image),location:section.location.clone()});
+        }
+        return Ok(());
+    }
+    fn toc(&mut self,toc:&Toc,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()> {
+        let section=Section {
+            content:self.outline.toc_markdown(toc),
+            style:toc.style.clone(),
+            tags:Vec::new(),
+            location:None,
+        };
+        return self.section(&section,parent_direction,out);
+    }
+    fn take_warnings(&mut self)->Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+impl Section {
+    /// Draws the content, leaving out images. Returns the destinations of the images.
+    fn draw(&self,rect:Rect,canvas:&mut Canvas,text:&TextStyle,options:Options,footnotes:&mut Footnotes)->Vec<String> {
+        let mut source=String::new();
+        for s in self.content.iter() {
+            source.push_str(s);
+            source.push('\n');
+        }
+        let mut layout=TextLayout::new(rect.w,text.clone(),footnotes);
+        for event in Parser::new_ext(&source,options) {
+            layout.event(event);
+        }
+        layout.finish_block(0.0);
+        let total_height=layout.lines.iter().map(|l|l.height).sum::<f32>();
+        let y=match text.vertical_align {
+            Some(VTextAlign::Center)=>rect.y+(rect.h-total_height).max(0.0)/2.0,
+            Some(VTextAlign::Bottom)=>rect.y+(rect.h-total_height).max(0.0),
+            _=>rect.y,
+        };
+        // Text that doesn't fit is cut off at the section's edges, instead of drawing over the
+        // sections around it.
+        canvas.clip(rect);
+        draw_lines(&layout.lines,rect,y,canvas,text);
+        canvas.restore();
+        return layout.images;
+    }
+}
+/// Draws footnotes in a smaller font along the bottom of `rect`, under a short rule.
+fn draw_footnotes(footnotes:Vec<Footnote>,rect:Rect,canvas:&mut Canvas,text:&TextStyle) {
+    if footnotes.is_empty() {
+        return;
+    }
+    let text=TextStyle {
+        font_size:text.font_size*0.8,
+        vertical_align:None,
+        horizontal_align:None,
+        ..text.clone()
+    };
+    let mut nested=Footnotes::default();
+    let mut layout=TextLayout::new(rect.w,text.clone(),&mut nested);
+    for footnote in footnotes {
+        layout.push_word(&format!("{}. ",footnote.number));
+        layout.push_text(&footnote.content);
+        layout.finish_line();
+    }
+    let total_height=layout.lines.iter().map(|l|l.height).sum::<f32>();
+    let y=rect.y+rect.h-total_height;
+    canvas.line(rect.x,y-text.font_size*0.5,rect.x+rect.w/3.0,y-text.font_size*0.5,0.5,text.color);
+    draw_lines(&layout.lines,rect,y,canvas,&text);
+}
+fn draw_lines(lines:&[Line],rect:Rect,mut y:f32,canvas:&mut Canvas,text:&TextStyle) {
+    for line in lines.iter() {
+        let x=match text.horizontal_align {
+            Some(HTextAlign::Center)=>rect.x+line.indent+(rect.w-line.indent-line.width).max(0.0)/2.0,
+            Some(HTextAlign::Right)=>rect.x+(rect.w-line.width).max(0.0),
+            _=>rect.x+line.indent,
+        };
+        if line.rule {
+            canvas.line(rect.x,y+line.height/2.0,rect.x+rect.w,y+line.height/2.0,0.5,text.color);
+        }
+        let baseline=y+(line.height-line.ascent)/2.0+line.ascent*0.8;
+        let mut run_x=x;
+        for run in line.runs.iter() {
+            canvas.text(run_x,baseline+run.rise,run,text.color);
+            if run.strike {
+                let strike_y=baseline-run.size*0.3;
+                canvas.line(run_x,strike_y,run_x+run.width,strike_y,run.size/16.0,text.color);
+            }
+            run_x+=run.width;
+        }
+        y+=line.height;
+    }
+}
+impl Size {
+    fn to_points(&self,parent:f32)->f32 {
+        use Size::*;
+        match self {
+            Inches(c)=>c*72.0,
+            Points(c)=>*c,
+            Pixels(c)=>c*0.75,
+            Millimeters(c)=>c*72.0/25.4,
+            Percent(c)=>parent*c/100.0,
+        }
+    }
+}
+impl Color {
+    fn to_rgb(&self)->(f32,f32,f32) {
+        (self.r as f32/255.0,self.g as f32/255.0,self.b as f32/255.0)
+    }
+}
+
+
+/// Splits `rect` between `items` like a flexbox: items with a size along the main axis keep it,
+/// and the rest share whatever space is left.
+fn layout_items(items:&[Item],rect:Rect,text:&TextStyle,horizontal:bool)->Vec<Slot> {
+    let main=if horizontal {rect.w} else {rect.h};
+    let cross=if horizontal {rect.h} else {rect.w};
+    let sizes=items.iter()
+        .map(|item|{
+            let style=item.style();
+            let size=if horizontal {
+                style.and_then(|s|s.width.as_ref())
+            } else {
+                style.and_then(|s|s.height.as_ref())
+            };
+            size.map(|s|s.to_points(main))
+        })
+        .collect::<Vec<_>>();
+    let fixed=sizes.iter().flatten().sum::<f32>();
+    let flexible=sizes.iter().filter(|s|s.is_none()).count();
+    let share=if flexible>0 {(main-fixed).max(0.0)/flexible as f32} else {0.0};
+    let mut offset=0.0;
+    let mut slots=Vec::new();
+    for (item,size) in items.iter().zip(sizes) {
+        let size=size.unwrap_or(share);
+        let style=item.style();
+        let cross_size=if horizontal {
+            style.and_then(|s|s.height.as_ref())
+        } else {
+            style.and_then(|s|s.width.as_ref())
+        }.map(|s|s.to_points(cross)).unwrap_or(cross);
+        let rect=if horizontal {
+            Rect{x:rect.x+offset,y:rect.y,w:size,h:cross_size}
+        } else {
+            Rect{x:rect.x,y:rect.y+offset,w:cross_size,h:size}
+        };
+        slots.push(Slot{rect,text:text.clone()});
+        offset+=size;
+    }
+    return slots;
+}
+fn number(n:f32)->String {
+    let s=format!("{:.3}",n);
+    return s.trim_end_matches('0').trim_end_matches('.').to_string();
+}
+/// Encodes `s` as a PDF literal string using the fonts' WinAnsiEncoding.
+fn pdf_string(s:&str)->Vec<u8> {
+    let mut out=vec![b'('];
+    for c in s.chars() {
+        match encode_char(c) {
+            b'('|b')'|b'\\'=>{
+                out.push(b'\\');
+                out.push(c as u8);
+            },
+            byte=>out.push(byte),
+        }
+    }
+    out.push(b')');
+    return out;
+}
+fn encode_char(c:char)->u8 {
+    match c {
+        ' '..='~'=>c as u8,
+        '\u{a0}'..='\u{ff}'=>c as u32 as u8,
+        '€'=>0x80,
+        '…'=>0x85,
+        '‘'=>0x91,
+        '’'=>0x92,
+        '“'=>0x93,
+        '”'=>0x94,
+        '•'=>0x95,
+        '–'=>0x96,
+        '—'=>0x97,
+        '\t'=>b' ',
+        _=>b'?',
+    }
+}
+fn char_width(c:char,font:Font)->f32 {
+    if font==Font::Mono {
+        return 0.6;
+    }
+    let width=match c {
+        ' '..='~'=>HELVETICA_WIDTHS[c as usize-0x20],
+        _=>556,
+    } as f32/1000.0;
+    if font.is_bold() {
+        return width*1.06;
+    }
+    return width;
+}
+
+
+/// The box an item is drawn into and the text style it inherits from its parents.
+struct Slot {
+    rect:Rect,
+    text:TextStyle,
+}
+#[derive(Copy,Clone)]
+struct Rect {
+    x:f32,
+    y:f32,
+    w:f32,
+    h:f32,
+}
+impl Rect {
+    fn inset(self,sides:Option<&SizedSides>)->Self {
+        let (left,right,top,bottom)=match sides {
+            Some(SizedSides::All(size))=>{
+                let h=size.to_points(self.w);
+                let v=size.to_points(self.h);
+                (h,h,v,v)
+            },
+            Some(SizedSides::Individual{left,right,top,bottom})=>(
+                left.as_ref().map(|s|s.to_points(self.w)).unwrap_or(0.0),
+                right.as_ref().map(|s|s.to_points(self.w)).unwrap_or(0.0),
+                top.as_ref().map(|s|s.to_points(self.h)).unwrap_or(0.0),
+                bottom.as_ref().map(|s|s.to_points(self.h)).unwrap_or(0.0),
+            ),
+            None=>return self,
+        };
+        return Rect {
+            x:self.x+left,
+            y:self.y+top,
+            w:(self.w-left-right).max(0.0),
+            h:(self.h-top-bottom).max(0.0),
+        };
+    }
+}
+#[derive(Clone)]
+struct TextStyle {
+    font_size:f32,
+    color:(f32,f32,f32),
+    monospace:bool,
+    vertical_align:Option<VTextAlign>,
+    horizontal_align:Option<HTextAlign>,
+}
+impl TextStyle {
+    fn inherit(&self,style:Option<&SectionStyle>)->Self {
+        let mut out=self.clone();
+        if let Some(style)=style {
+            if let Some(size)=&style.font_size {
+                out.font_size=size.to_points(self.font_size);
+            }
+            if let Some(color)=&style.text_color {
+                out.color=color.to_rgb();
+            }
+            if let Some(font)=&style.font {
+                let font=font.to_lowercase();
+                out.monospace=font.contains("mono")||font.contains("code")||font.contains("courier");
+            }
+            if style.vertical_text_align.is_some() {
+                out.vertical_align=style.vertical_text_align;
+            }
+            if style.horizontal_text_align.is_some() {
+                out.horizontal_align=style.horizontal_text_align;
+            }
+        }
+        return out;
+    }
+}
+#[derive(Copy,Clone,PartialEq)]
+enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+impl Font {
+    fn new(bold:bool,italic:bool,mono:bool)->Self {
+        match (bold,italic,mono) {
+            (_,_,true)=>Font::Mono,
+            (true,true,_)=>Font::BoldItalic,
+            (true,false,_)=>Font::Bold,
+            (false,true,_)=>Font::Italic,
+            (false,false,_)=>Font::Regular,
+        }
+    }
+    fn is_bold(&self)->bool {
+        *self==Font::Bold||*self==Font::BoldItalic
+    }
+    fn resource(&self)->&'static str {
+        match self {
+            Font::Regular=>"F1",
+            Font::Bold=>"F2",
+            Font::Italic=>"F3",
+            Font::BoldItalic=>"F4",
+            Font::Mono=>"F5",
+        }
+    }
+}
+struct Run {
+    text:String,
+    font:Font,
+    size:f32,
+    width:f32,
+    rise:f32,
+    strike:bool,
+}
+#[derive(Default)]
+struct Line {
+    runs:Vec<Run>,
+    width:f32,
+    height:f32,
+    ascent:f32,
+    indent:f32,
+    rule:bool,
+}
+/// Turns markdown events into wrapped lines of text. Footnote definitions are set aside as plain
+/// text in `footnotes` to be drawn at the bottom of the page.
+struct TextLayout<'a> {
+    max_width:f32,
+    base:TextStyle,
+    lines:Vec<Line>,
+    current:Line,
+    lists:Vec<Option<u64>>,
+    heading_scale:Option<f32>,
+    bold:usize,
+    italic:usize,
+    strike:usize,
+    superscript:bool,
+    code_block:bool,
+    table_cell:usize,
+    footnotes:&'a mut Footnotes,
+    /// The label and text of the footnote definition being read.
+    footnote:Option<(String,String)>,
+    /// The destinations of the images that were left out.
+    images:Vec<String>,
+}
+impl<'a> TextLayout<'a> {
+    fn new(max_width:f32,base:TextStyle,footnotes:&'a mut Footnotes)->Self {
+        TextLayout {
+            max_width,
+            base,
+            lines:Vec::new(),
+            current:Line::default(),
+            lists:Vec::new(),
+            heading_scale:None,
+            bold:0,
+            italic:0,
+            strike:0,
+            superscript:false,
+            code_block:false,
+            table_cell:0,
+            footnotes,
+            footnote:None,
+            images:Vec::new(),
+        }
+    }
+    fn event(&mut self,event:Event) {
+        use Event::*;
+        if let Some((label,content))=&mut self.footnote {
+            match event {
+                Text(text)|Code(text)=>content.push_str(&text),
+                SoftBreak|HardBreak|End(Tag::Paragraph)=>content.push(' '),
+                End(Tag::FootnoteDefinition(_))=>{
+                    let content=content.trim().to_string();
+                    self.footnotes.define(label,content);
+                    self.footnote=None;
+                },
+                _=>{},
+            }
+            return;
+        }
+        match event {
+            Start(tag)=>{
+                use Tag::*;
+                match tag {
+                    Heading(level,_,_)=>{
+                        use HeadingLevel::*;
+                        self.finish_line();
+                        self.heading_scale=Some(match level {
+                            H1=>2.0,
+                            H2=>1.5,
+                            H3=>1.17,
+                            H4=>1.0,
+                            H5=>0.83,
+                            H6=>0.67,
+                        });
+                        self.bold+=1;
+                    },
+                    List(start)=>{
+                        self.finish_line();
+                        self.lists.push(start);
+                    },
+                    Item=>{
+                        self.finish_line();
+                        let prefix=match self.lists.last_mut() {
+                            Some(Some(n))=>{
+                                *n+=1;
+                                format!("{}. ",*n-1)
+                            },
+                            _=>"• ".to_string(),
+                        };
+                        self.push_word(&prefix);
+                    },
+                    CodeBlock(_)=>{
+                        self.finish_line();
+                        self.code_block=true;
+                    },
+                    Emphasis=>self.italic+=1,
+                    Strong=>self.bold+=1,
+                    Strikethrough=>self.strike+=1,
+                    Image(_,dest,_)=>self.images.push(dest.to_string()),
+                    TableHead=>{
+                        self.finish_line();
+                        self.table_cell=0;
+                        self.bold+=1;
+                    },
+                    TableRow=>{
+                        self.finish_line();
+                        self.table_cell=0;
+                    },
+                    TableCell=>{
+                        if self.table_cell>0 {
+                            self.push_word(" | ");
+                        }
+                        self.table_cell+=1;
+                    },
+                    FootnoteDefinition(label)=>{
+                        self.finish_line();
+                        self.footnote=Some((label.to_string(),String::new()));
+                    },
+                    _=>{},
+                }
+            },
+            End(tag)=>{
+                use Tag::*;
+                match tag {
+                    Paragraph|Table(_)=>self.finish_block(0.5),
+                    TableHead=>{
+                        self.bold-=1;
+                        self.finish_line();
+                    },
+                    TableRow=>self.finish_line(),
+                    Heading(..)=>{
+                        self.finish_block(0.3);
+                        self.heading_scale=None;
+                        self.bold-=1;
+                    },
+                    List(_)=>{
+                        self.lists.pop();
+                        self.finish_block(0.5);
+                    },
+                    Item=>self.finish_line(),
+                    CodeBlock(_)=>{
+                        self.code_block=false;
+                        self.finish_block(0.5);
+                    },
+                    Emphasis=>self.italic-=1,
+                    Strong=>self.bold-=1,
+                    Strikethrough=>self.strike-=1,
+                    _=>{},
+                }
+            },
+            Text(text)=>{
+                if self.code_block {
+                    let mut lines=text.split('\n').peekable();
+                    while let Some(line)=lines.next() {
+                        self.push_preformatted(line);
+                        if lines.peek().is_some() {
+                            self.finish_line();
+                        }
+                    }
+                } else {
+                    self.push_text(&text);
+                }
+            },
+            Code(code)=>{
+                self.code_block=true;
+                self.push_text(&code);
+                self.code_block=false;
+            },
+            FootnoteReference(label)=>{
+                let (number,_)=self.footnotes.reference(&label);
+                self.superscript=true;
+                self.push_word(&number.to_string());
+                self.superscript=false;
+            },
+            TaskListMarker(checked)=>{
+                if checked {
+                    self.push_word("[x] ");
+                } else {
+                    self.push_word("[ ] ");
+                }
+            },
+            SoftBreak|HardBreak=>self.finish_line(),
+            Rule=>{
+                self.finish_line();
+                self.current.rule=true;
+                self.current.height=self.base.font_size;
+                self.finish_line();
+            },
+            _=>{},
+        }
+    }
+    fn indent(&self)->f32 {
+        self.lists.len() as f32*LIST_INDENT
+    }
+    fn font(&self)->Font {
+        Font::new(self.bold>0,self.italic>0,self.code_block||self.base.monospace)
+    }
+    fn size(&self)->f32 {
+        let size=self.base.font_size*self.heading_scale.unwrap_or(1.0);
+        if self.superscript {
+            return size*0.6;
+        }
+        return size;
+    }
+    fn push_text(&mut self,text:&str) {
+        let mut start=0;
+        for (i,c) in text.char_indices() {
+            if c==' ' {
+                self.push_word(&text[start..i+1]);
+                start=i+1;
+            }
+        }
+        if start<text.len() {
+            self.push_word(&text[start..]);
+        }
+    }
+    /// Code is never wrapped at spaces, but is broken by character when it does not fit.
+    fn push_preformatted(&mut self,text:&str) {
+        let font=self.font();
+        let size=self.size();
+        let mut start=0;
+        let mut width=0.0;
+        for (i,c) in text.char_indices() {
+            let w=char_width(c,font)*size;
+            if self.current.width+width+w>self.available()&&i>start {
+                self.push_run(&text[start..i],width);
+                self.finish_line();
+                start=i;
+                width=0.0;
+            }
+            width+=w;
+        }
+        self.push_run(&text[start..],width);
+    }
+    fn push_word(&mut self,word:&str) {
+        let font=self.font();
+        let size=self.size();
+        let width=word.chars().map(|c|char_width(c,font)*size).sum::<f32>();
+        if self.current.width+width>self.available()&&!self.current.runs.is_empty() {
+            self.finish_line();
+            let word=word.trim_start();
+            let width=word.chars().map(|c|char_width(c,font)*size).sum::<f32>();
+            self.push_run(word,width);
+        } else {
+            self.push_run(word,width);
+        }
+    }
+    fn push_run(&mut self,text:&str,width:f32) {
+        let font=self.font();
+        let size=self.size();
+        if self.current.runs.is_empty() {
+            self.current.indent=self.indent();
+        }
+        let rise=if self.superscript {-self.base.font_size*0.35} else {0.0};
+        if let Some(last)=self.current.runs.last_mut() {
+            if last.font==font&&last.size==size&&last.rise==rise&&last.strike==(self.strike>0) {
+                last.text.push_str(text);
+                last.width+=width;
+                self.current.width+=width;
+                return;
+            }
+        }
+        self.current.runs.push(Run{text:text.to_string(),font,size,width,rise,strike:self.strike>0});
+        self.current.width+=width;
+        self.current.ascent=self.current.ascent.max(size);
+        self.current.height=self.current.height.max(size*LINE_HEIGHT);
+    }
+    fn available(&self)->f32 {
+        self.max_width-self.indent()
+    }
+    fn finish_line(&mut self) {
+        if self.current.runs.is_empty()&&!self.current.rule {
+            return;
+        }
+        let line=std::mem::take(&mut self.current);
+        self.lines.push(line);
+    }
+    /// Ends a block of text and adds `spacing` ems of space after it.
+    fn finish_block(&mut self,spacing:f32) {
+        self.finish_line();
+        if spacing>0.0 {
+            self.lines.push(Line{height:self.base.font_size*spacing,..Line::default()});
+        }
+    }
+}
+/// Builds a page's content stream. Coordinates are given from the top left like the HTML output
+/// and flipped into PDF space here.
+struct Canvas {
+    height:f32,
+    out:Vec<u8>,
+}
+impl Canvas {
+    fn fill_rect(&mut self,rect:Rect,color:&Color) {
+        let (r,g,b)=color.to_rgb();
+        let op=format!(
+            "{} {} {} rg {} {} {} {} re f\n",
+            number(r),number(g),number(b),
+            number(rect.x),number(self.height-rect.y-rect.h),number(rect.w),number(rect.h),
+        );
+        self.out.extend(op.into_bytes());
+    }
+    /// Saves the graphics state and limits drawing to `rect` until [`restore`](Self::restore).
+    fn clip(&mut self,rect:Rect) {
+        let op=format!(
+            "q {} {} {} {} re W n\n",
+            number(rect.x),number(self.height-rect.y-rect.h),number(rect.w),number(rect.h),
+        );
+        self.out.extend(op.into_bytes());
+    }
+    fn restore(&mut self) {
+        self.out.extend(b"Q\n");
+    }
+    fn line(&mut self,x1:f32,y1:f32,x2:f32,y2:f32,width:f32,color:(f32,f32,f32)) {
+        let op=format!(
+            "{} {} {} RG {} w {} {} m {} {} l S\n",
+            number(color.0),number(color.1),number(color.2),
+            number(width),
+            number(x1),number(self.height-y1),
+            number(x2),number(self.height-y2),
+        );
+        self.out.extend(op.into_bytes());
+    }
+    fn text(&mut self,x:f32,baseline:f32,run:&Run,color:(f32,f32,f32)) {
+        let op=format!(
+            "BT /{} {} Tf {} {} {} rg {} {} Td ",
+            run.font.resource(),
+            number(run.size),
+            number(color.0),number(color.1),number(color.2),
+            number(x),number(self.height-baseline),
+        );
+        self.out.extend(op.into_bytes());
+        self.out.extend(pdf_string(&run.text));
+        self.out.extend(b" Tj ET\n");
+    }
+}
+/// A minimal PDF 1.4 file writer. Objects are numbered from 1 in the order they are reserved.
+#[derive(Default)]
+struct PdfWriter {
+    objects:Vec<Vec<u8>>,
+}
+impl PdfWriter {
+    fn reserve(&mut self)->usize {
+        self.objects.push(Vec::new());
+        return self.objects.len();
+    }
+    fn set(&mut self,id:usize,body:Vec<u8>) {
+        self.objects[id-1]=body;
+    }
+    fn add(&mut self,body:Vec<u8>)->usize {
+        self.objects.push(body);
+        return self.objects.len();
+    }
+    fn add_stream(&mut self,data:Vec<u8>)->usize {
+        let mut body=format!("<< /Length {} >>\nstream\n",data.len()).into_bytes();
+        body.extend(data);
+        body.extend(b"\nendstream");
+        return self.add(body);
+    }
+    fn finish(self,root:usize,info:usize)->Vec<u8> {
+        let mut out=b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets=Vec::new();
+        for (i,body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend(format!("{} 0 obj\n",i+1).into_bytes());
+            out.extend(body);
+            out.extend(b"\nendobj\n");
+        }
+        let xref=out.len();
+        out.extend(format!("xref\n0 {}\n0000000000 65535 f \n",offsets.len()+1).into_bytes());
+        for offset in offsets {
+            out.extend(format!("{:010} 00000 n \n",offset).into_bytes());
+        }
+        out.extend(format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.objects.len()+1,
+            root,
+            info,
+            xref,
+        ).into_bytes());
+        return out;
+    }
+}
@@ -2,22 +2,34 @@ use pulldown_cmark::{
     Parser,
     Event,
     Tag,
-    Options,
     CodeBlockKind,
-    HeadingLevel,
     LinkType,
+    Alignment,
+};
+use std::io::{
+    Write,
+    Result as IoResult,
+};
+use crate::highlight::Highlighter;
+use crate::warning::Warning;
+use crate::outline::{
+    Outline,
+    Footnotes,
+    Footnote,
 };
 use crate::parser::{
     Document,
-    Metadata,
     Page,
     PageStyle,
     Section,
     SectionStyle,
     Item,
+    Toc,
+    MarkdownOptions,
+    FootnotePlacement,
+    Var,
     Size,
     Color,
-    PageSize,
     SizedSides,
     Direction,
     VTextAlign,
@@ -25,27 +37,84 @@ use crate::parser::{
 };
 
 
-pub trait IntoHtml {
-    fn into_html(self,parent_direction:ParentDirection)->String;
+/// An output backend. Implementors only handle the individual parts of a document; the recursive
+/// walk over pages and items is shared by every backend through [`Renderer::render`].
+pub trait Renderer {
+    fn begin_document(&mut self,document:&Document,out:&mut dyn Write)->IoResult<()>;
+    fn end_document(&mut self,document:&Document,out:&mut dyn Write)->IoResult<()>;
+    fn begin_page(&mut self,page:&Page,out:&mut dyn Write)->IoResult<()>;
+    fn end_page(&mut self,page:&Page,out:&mut dyn Write)->IoResult<()>;
+    /// Starts a `horizontal` or `vertical` item. `direction` is the direction of the container
+    /// itself, and `items` are its children, which are visited next.
+    fn begin_container(&mut self,direction:ParentDirection,items:&[Item],style:Option<&SectionStyle>,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()>;
+    fn end_container(&mut self,direction:ParentDirection,out:&mut dyn Write)->IoResult<()>;
+    fn section(&mut self,section:&Section,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()>;
+    fn toc(&mut self,toc:&Toc,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()>;
+    /// Takes the warnings from rendering, like about content the backend can't show.
+    fn take_warnings(&mut self)->Vec<Warning> {
+        Vec::new()
+    }
+
+    fn render(&mut self,document:&Document,out:&mut dyn Write)->IoResult<()> {
+        self.begin_document(document,out)?;
+        for page in document.pages.iter() {
+            self.begin_page(page,out)?;
+            for item in page.items.iter() {
+                self.item(item,ParentDirection::None,out)?;
+            }
+            self.end_page(page,out)?;
+        }
+        return self.end_document(document,out);
+    }
+    fn item(&mut self,item:&Item,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()> {
+        use Item::*;
+        let (direction,items,style)=match item {
+            Horizontal{items,style,..}=>(ParentDirection::Horizontal,items,style),
+            Vertical{items,style,..}=>(ParentDirection::Vertical,items,style),
+            Section(s)=>return self.section(s,parent_direction,out),
+            Toc(toc)=>return self.toc(toc,parent_direction,out),
+        };
+        self.begin_container(direction,items,style.as_ref(),parent_direction,out)?;
+        for item in items.iter() {
+            self.item(item,direction,out)?;
+        }
+        return self.end_container(direction,out);
+    }
+}
+pub trait ToCss {
+    fn to_css(&self,parent_direction:ParentDirection)->String;
 }
-impl IntoHtml for Size {
-    fn into_html(self,_:ParentDirection)->String {
+impl ToCss for Size {
+    fn to_css(&self,_:ParentDirection)->String {
         use Size::*;
         match self {
             Inches(c)=>format!("{}in",c),
             Points(c)=>format!("{}pt",c),
             Pixels(c)=>format!("{}px",c),
+            Millimeters(c)=>format!("{}mm",c),
             Percent(c)=>format!("{}%",c),
         }
     }
 }
-impl IntoHtml for Color {
-    fn into_html(self,_:ParentDirection)->String {
+impl ToCss for Var {
+    fn to_css(&self,parent_direction:ParentDirection)->String {
+        match self {
+            Var::Color(color)=>color.to_css(parent_direction),
+            Var::Size(size)=>size.to_css(parent_direction),
+            Var::Text(text)=>{
+                let escaped=text.replace('\\',"\\\\").replace('"',"\\\"").replace('<',"\\3c ");
+                format!("\"{}\"",escaped)
+            },
+        }
+    }
+}
+impl ToCss for Color {
+    fn to_css(&self,_:ParentDirection)->String {
         format!("#{:02X}{:02X}{:02X}{:02X}",self.r,self.g,self.b,self.a.unwrap_or(0xff))
     }
 }
-impl IntoHtml for Direction {
-    fn into_html(self,_:ParentDirection)->String {
+impl ToCss for Direction {
+    fn to_css(&self,_:ParentDirection)->String {
         use Direction::*;
         match self {
             Left=>"left",
@@ -55,40 +124,43 @@ impl IntoHtml for Direction {
         }.to_string()
     }
 }
-impl IntoHtml for PageSize {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        use PageSize::*;
-        match self {
-            PortraitLetter=>format!("width:8.5in;height:11in;"),
-            LandscapeLetter=>format!("width:11in;height:8.5in;"),
-            Webpage=>format!("width:100%;height:100%;"),
-            Custom{width,height}=>format!("width:{};height:{};",width.into_html(parent_direction),height.into_html(parent_direction)),
-        }
+/// The value for an `@page` rule's `size`, if the page has a fixed size that can be printed.
+fn print_size(dimensions:Option<(Size,Size)>)->Option<String> {
+    let (width,height)=dimensions?;
+    if matches!(width,Size::Percent(_))||matches!(height,Size::Percent(_)) {
+        return None;
+    }
+    return Some(format!("{} {}",width.to_css(ParentDirection::None),height.to_css(ParentDirection::None)));
+}
+fn dimensions_css(dimensions:Option<(Size,Size)>,parent_direction:ParentDirection)->String {
+    match dimensions {
+        Some((width,height))=>format!("width:{};height:{};",width.to_css(parent_direction),height.to_css(parent_direction)),
+        None=>format!("width:100%;height:100%;"),
     }
 }
-impl IntoHtml for SizedSides {
-    fn into_html(self,parent_direction:ParentDirection)->String {
+impl ToCss for SizedSides {
+    fn to_css(&self,parent_direction:ParentDirection)->String {
         use SizedSides::*;
         match self {
             All(size)=>{
-                return format!("NAME:{};",size.into_html(parent_direction));
+                return format!("NAME:{};",size.to_css(parent_direction));
             },
             Individual{left,right,top,bottom}=>{
                 let mut out=String::new();
                 if let Some(size)=left {
-                    let fmt=format!("NAME-left:{};",size.into_html(parent_direction));
+                    let fmt=format!("NAME-left:{};",size.to_css(parent_direction));
                     out.push_str(&fmt);
                 }
                 if let Some(size)=right {
-                    let fmt=format!("NAME-right:{};",size.into_html(parent_direction));
+                    let fmt=format!("NAME-right:{};",size.to_css(parent_direction));
                     out.push_str(&fmt);
                 }
                 if let Some(size)=top {
-                    let fmt=format!("NAME-top:{};",size.into_html(parent_direction));
+                    let fmt=format!("NAME-top:{};",size.to_css(parent_direction));
                     out.push_str(&fmt);
                 }
                 if let Some(size)=bottom {
-                    let fmt=format!("NAME-bottom:{};",size.into_html(parent_direction));
+                    let fmt=format!("NAME-bottom:{};",size.to_css(parent_direction));
                     out.push_str(&fmt);
                 }
                 return out;
@@ -96,8 +168,8 @@ impl IntoHtml for SizedSides {
         }
     }
 }
-impl IntoHtml for VTextAlign {
-    fn into_html(self,_:ParentDirection)->String {
+impl ToCss for VTextAlign {
+    fn to_css(&self,_:ParentDirection)->String {
         use VTextAlign::*;
         match self {
             Top=>"top",
@@ -106,8 +178,8 @@ impl IntoHtml for VTextAlign {
         }.to_string()
     }
 }
-impl IntoHtml for HTextAlign {
-    fn into_html(self,_:ParentDirection)->String {
+impl ToCss for HTextAlign {
+    fn to_css(&self,_:ParentDirection)->String {
         use HTextAlign::*;
         match self {
             Left=>"left",
@@ -116,8 +188,8 @@ impl IntoHtml for HTextAlign {
         }.to_string()
     }
 }
-impl IntoHtml for SectionStyle {
-    fn into_html(self,parent_direction:ParentDirection)->String {
+impl ToCss for SectionStyle {
+    fn to_css(&self,parent_direction:ParentDirection)->String {
         let mut out=String::new();
         out.push_str("padding:0;");
         out.push_str("margin:0;");
@@ -129,62 +201,62 @@ impl IntoHtml for SectionStyle {
             out.push_str("flex-grow:0;");
             out.push_str("flex-shrink:0;");
         }
-        if let Some(width)=self.width {
-            let fmt=format!("width:{};",width.into_html(parent_direction));
+        if let Some(width)=&self.width {
+            let fmt=format!("width:{};",width.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(height)=self.height {
-            let fmt=format!("height:{};",height.into_html(parent_direction));
+        if let Some(height)=&self.height {
+            let fmt=format!("height:{};",height.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(align)=self.align {
-            let fmt=format!("float:{};",align.into_html(parent_direction));
+        if let Some(align)=&self.align {
+            let fmt=format!("float:{};",align.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(font)=self.font {
-            let fmt=format!("font-family:{};",font);
+        if let Some(font)=&self.font {
+            let fmt=format!("font-family:{};",escape(&font_family(font)));
             out.push_str(&fmt);
         }
-        if let Some(font_size)=self.font_size {
-            let fmt=format!("font-size:{};",font_size.into_html(parent_direction));
+        if let Some(font_size)=&self.font_size {
+            let fmt=format!("font-size:{};",font_size.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(text_color)=self.text_color {
-            let fmt=format!("color:{};",text_color.into_html(parent_direction));
+        if let Some(text_color)=&self.text_color {
+            let fmt=format!("color:{};",text_color.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(background_color)=self.background_color {
-            let fmt=format!("background-color:{};",background_color.into_html(parent_direction));
+        if let Some(background_color)=&self.background_color {
+            let fmt=format!("background-color:{};",background_color.to_css(parent_direction));
             out.push_str(&fmt);
         }
-        if let Some(margin)=self.margin {
-            let fmt=margin.into_html(parent_direction).replace("NAME","margin");
+        if let Some(margin)=&self.margin {
+            let fmt=margin.to_css(parent_direction).replace("NAME","margin");
             out.push_str(&fmt);
         }
         let mut need_flexbox=false;
-        if let Some(align)=self.vertical_text_align {
-            let fmt=if align==VTextAlign::Center {
+        if let Some(align)=&self.vertical_text_align {
+            let fmt=if *align==VTextAlign::Center {
                 need_flexbox=true;
                 if parent_direction==ParentDirection::Horizontal {
-                    format!("align-items:{};",align.into_html(parent_direction))
+                    format!("align-items:{};",align.to_css(parent_direction))
                 } else {
-                    format!("justify-content:{};",align.into_html(parent_direction))
+                    format!("justify-content:{};",align.to_css(parent_direction))
                 }
             } else {
-                format!("vertical-align:{};",align.into_html(parent_direction))
+                format!("vertical-align:{};",align.to_css(parent_direction))
             };
             out.push_str(&fmt);
         }
-        if let Some(align)=self.horizontal_text_align {
-            let fmt=if align==HTextAlign::Center {
+        if let Some(align)=&self.horizontal_text_align {
+            let fmt=if *align==HTextAlign::Center {
                 need_flexbox=true;
                 if parent_direction==ParentDirection::Horizontal {
-                    format!("justify-content:{};",align.into_html(parent_direction))
+                    format!("justify-content:{};",align.to_css(parent_direction))
                 } else {
-                    format!("align-items:{};",align.into_html(parent_direction))
+                    format!("align-items:{};",align.to_css(parent_direction))
                 }
             } else {
-                format!("text-align:{};",align.into_html(parent_direction))
+                format!("text-align:{};",align.to_css(parent_direction))
             };
             out.push_str(&fmt);
         }
@@ -194,35 +266,192 @@ impl IntoHtml for SectionStyle {
         return out;
     }
 }
-impl IntoHtml for Section {
-    fn into_html(self,parent_direction:ParentDirection)->String {
+impl ToCss for PageStyle {
+    fn to_css(&self,parent_direction:ParentDirection)->String {
+        let mut out=String::new();
+        if self.page_size.is_some()||self.orientation.is_some() {
+            let fmt=dimensions_css(self.dimensions(None),parent_direction);
+            out.push_str(&fmt);
+        }
+        if let Some(text_color)=&self.text_color {
+            let fmt=format!("color:{};",text_color.to_css(parent_direction));
+            out.push_str(&fmt);
+        }
+        if let Some(background_color)=&self.background_color {
+            let fmt=format!("background-color:{};",background_color.to_css(parent_direction));
+            out.push_str(&fmt);
+        }
+        if let Some(margin)=&self.margin {
+            let fmt=margin.to_css(parent_direction).replace("NAME","padding");
+            out.push_str(&fmt);
+        }
+        out.push_str("margin:0;");
+        return out;
+    }
+}
+/// Renders documents into a single self-contained HTML page.
+#[derive(Default)]
+pub struct HtmlRenderer {
+    highlighter:Option<Highlighter>,
+    markdown:MarkdownOptions,
+    outline:Outline,
+    next_heading:usize,
+    footnotes:Footnotes,
+    footnote_placement:FootnotePlacement,
+    page:usize,
+    page_count:usize,
+    default_page_style:Option<PageStyle>,
+    /// The distinct printable page sizes. Each gets a named `@page` rule.
+    print_sizes:Vec<String>,
+    safe:bool,
+}
+impl HtmlRenderer {
+    /// A renderer for documents we did not write ourselves. Raw HTML in the markdown is dropped,
+    /// and links or images using a scriptable URL scheme are left out.
+    pub fn safe()->Self {
+        HtmlRenderer{safe:true,..Self::default()}
+    }
+    fn dimensions(&self,page:&Page)->Option<(Size,Size)> {
+        let default=self.default_page_style.as_ref();
+        match &page.style {
+            Some(style)=>style.dimensions(default),
+            None=>default.and_then(|d|d.dimensions(None)),
+        }
+    }
+    fn url(&self,url:&str)->Option<String> {
+        if self.safe&&!is_safe_url(url) {
+            return None;
+        }
+        return Some(escape(url));
+    }
+}
+impl Renderer for HtmlRenderer {
+    fn begin_document(&mut self,document:&Document,out:&mut dyn Write)->IoResult<()> {
+        let metadata=&document.metadata;
+        let page_style=metadata.page_style.as_ref().map(|s|s.to_css(ParentDirection::None)).unwrap_or_default();
+        let highlighter=Highlighter::new(metadata.code_theme.as_deref(),metadata.code_highlight.unwrap_or_default());
+        self.default_page_style=metadata.page_style.clone();
+        self.print_sizes.clear();
+        for page in document.pages.iter() {
+            if let Some(size)=print_size(self.dimensions(page)) {
+                if !self.print_sizes.contains(&size) {
+                    self.print_sizes.push(size);
+                }
+            }
+        }
+        write!(out,"<!DOCTYPE html><html><head>")?;
+        write!(out,"<title>{}</title><style>html{{height:100%;width:100%}}body{{height:100%;width:100%}}.page{{display:flex;box-sizing:border-box;print-color-adjust:exact;-webkit-print-color-adjust:exact;{}}}",escape(&metadata.title),page_style)?;
+        // The page margins are the `.page` padding, so the sheets themselves have none.
+        match print_size(metadata.page_style.as_ref().and_then(|s|s.dimensions(None))) {
+            Some(size)=>write!(out,"@page{{size:{};margin:0}}",size)?,
+            None=>write!(out,"@page{{margin:0}}")?,
+        }
+        for (i,size) in self.print_sizes.iter().enumerate() {
+            write!(out,"@page page-{}{{size:{};margin:0}}",i+1,size)?;
+        }
+        // The variables are inlined where they are used, but custom properties let raw HTML use them too.
+        if metadata.vars.len()>0 {
+            write!(out,":root{{")?;
+            for (name,value) in metadata.vars.iter() {
+                write!(out,"--{}:{};",name,value.to_css(ParentDirection::None))?;
+            }
+            write!(out,"}}")?;
+        }
+        if let Some(css)=highlighter.css() {
+            out.write_all(css.as_bytes())?;
+        }
+        write!(out,"</style>")?;
+        self.highlighter=Some(highlighter);
+        self.markdown=metadata.markdown.clone().unwrap_or_default();
+        self.outline=Outline::new(document);
+        self.next_heading=0;
+        self.footnotes=Footnotes::default();
+        self.footnote_placement=metadata.footnotes.unwrap_or_default();
+        self.page=0;
+        self.page_count=document.pages.len();
+        write!(out,"</head><body style=\"margin:0;padding:0\">")?;
+        return Ok(());
+    }
+    fn end_document(&mut self,_:&Document,out:&mut dyn Write)->IoResult<()> {
+        let footnotes=self.footnotes.take();
+        if footnotes.len()>0 {
+            write!(out,"<div class=\"footnotes\">{}</div>",footnotes_html(footnotes))?;
+        }
+        write!(out,"</body></html>")
+    }
+    fn begin_page(&mut self,page:&Page,out:&mut dyn Write)->IoResult<()> {
+        self.page+=1;
+        let mut page_style=page.style.clone().unwrap_or_default();
+        if page_style.page_size.is_none()&&page_style.orientation.is_some() {
+            // Only the orientation changed, so the size comes from the document's page style.
+            page_style.page_size=self.default_page_style.as_ref().and_then(|s|s.page_size.clone());
+        }
+        let mut style=page_style.to_css(ParentDirection::None);
+        if self.footnote_placement==FootnotePlacement::Page&&self.outline.page_has_footnotes(self.page) {
+            // The footnotes are positioned against the bottom of the page.
+            style.push_str("position:relative;");
+        }
+        if let Some(size)=print_size(self.dimensions(page)) {
+            let index=self.print_sizes.iter().position(|s|*s==size).unwrap_or(0);
+            let fmt=format!("page:page-{};",index+1);
+            style.push_str(&fmt);
+        }
+        if self.page<self.page_count {
+            style.push_str("page-break-after:always;");
+        }
+        write!(out,"<div style=\"{}\" class=\"page\">",style)
+    }
+    fn end_page(&mut self,_:&Page,out:&mut dyn Write)->IoResult<()> {
+        if self.footnote_placement==FootnotePlacement::Page {
+            let footnotes=self.footnotes.take();
+            if footnotes.len()>0 {
+                write!(out,"<div class=\"footnotes\" style=\"position:absolute;left:0;right:0;bottom:0;font-size:0.8em\">{}</div>",footnotes_html(footnotes))?;
+            }
+        }
+        write!(out,"</div>")
+    }
+    fn begin_container(&mut self,direction:ParentDirection,_:&[Item],style:Option<&SectionStyle>,_:ParentDirection,out:&mut dyn Write)->IoResult<()> {
+        let style=style.map(|s|s.to_css(direction)).unwrap_or_else(||SectionStyle::default().to_css(direction));
+        let flex_direction=match direction {
+            ParentDirection::Horizontal=>"row",
+            _=>"column",
+        };
+        write!(out,"<div style=\"{}align-items:stretch;display:flex;flex-direction:{};\">",style,flex_direction)
+    }
+    fn end_container(&mut self,_:ParentDirection,out:&mut dyn Write)->IoResult<()> {
+        write!(out,"</div>")
+    }
+    fn section(&mut self,section:&Section,parent_direction:ParentDirection,writer:&mut dyn Write)->IoResult<()> {
         let mut size_style=SectionStyle::default();
-        let mut self_style=self.style.unwrap_or_default();
+        let mut self_style=section.style.clone().unwrap_or_default();
         size_style.width=self_style.width.take();
         size_style.height=self_style.height.take();
         size_style.align=self_style.align.take();
-        size_style.vertical_text_align=self_style.vertical_text_align.clone();
-        size_style.horizontal_text_align=self_style.horizontal_text_align.clone();
+        size_style.vertical_text_align=self_style.vertical_text_align;
+        size_style.horizontal_text_align=self_style.horizontal_text_align;
         size_style.background_color=self_style.background_color.take();
-        let mut out=format!("<div style=\"{}\"><div style=\"{}\">",size_style.into_html(parent_direction),self_style.into_html(parent_direction));
+        let mut out=format!("<div style=\"{}\"><div style=\"{}\">",size_style.to_css(parent_direction),self_style.to_css(parent_direction));
         let mut source=String::new();
-        for s in self.content {
-            source.push_str(&s);
+        for s in section.content.iter() {
+            source.push_str(s);
             source.push('\n');
         }
         let mut code:Option<(Option<String>,String)>=None;
-        let mut in_footnote=false;
+        // The label and where the definition starts in `out`. It is moved out once it ends.
+        let mut footnote:Option<(String,usize)>=None;
+        let mut table_alignments=Vec::new();
+        let mut table_cell=0;
+        let mut in_table_head=false;
+        let options=self.markdown.merge(section.style.as_ref().and_then(|s|s.markdown.as_ref())).options();
         //println!("-------------------------------");
-        for event in Parser::new_ext(&source,Options::ENABLE_STRIKETHROUGH|Options::ENABLE_FOOTNOTES) {
+        for event in Parser::new_ext(&source,options) {
             //println!("Markdown item: {:?}",event);
             use Event::*;
             match event {
                 Start(tag)=>{
                     use Tag::*;
                     match tag {
-                        Paragraph=>{
-                            if !in_footnote {out.push_str("<p>")}
-                        },
+                        Paragraph=>out.push_str("<p>"),
                         BlockQuote=>out.push_str("<blockquote>"),
                         List(start)=>{
                             if let Some(start)=start {
@@ -233,16 +462,42 @@ impl IntoHtml for Section {
                             }
                         },
                         Item=>out.push_str("<li>"),
-                        Heading(level,_,_)=>{
-                            use HeadingLevel::*;
-                            match level {
-                                H1=>out.push_str("<h1>"),
-                                H2=>out.push_str("<h2>"),
-                                H3=>out.push_str("<h3>"),
-                                H4=>out.push_str("<h4>"),
-                                H5=>out.push_str("<h5>"),
-                                H6=>out.push_str("<h6>"),
+                        Heading(level,_,classes)=>{
+                            let mut fmt=format!("<{}",level);
+                            // The outline already took any explicit id into account.
+                            if let Some(heading)=self.outline.headings.get(self.next_heading) {
+                                fmt.push_str(&format!(" id=\"{}\"",escape(&heading.id)));
+                            }
+                            self.next_heading+=1;
+                            if classes.len()>0 {
+                                fmt.push_str(&format!(" class=\"{}\"",escape(&classes.join(" "))));
                             }
+                            fmt.push('>');
+                            out.push_str(&fmt);
+                        },
+                        Table(alignments)=>{
+                            table_alignments=alignments;
+                            out.push_str("<table>");
+                        },
+                        TableHead=>{
+                            in_table_head=true;
+                            table_cell=0;
+                            out.push_str("<thead><tr>");
+                        },
+                        TableRow=>{
+                            table_cell=0;
+                            out.push_str("<tr>");
+                        },
+                        TableCell=>{
+                            let tag=if in_table_head {"th"} else {"td"};
+                            let align=match table_alignments.get(table_cell) {
+                                Some(Alignment::Left)=>" style=\"text-align:left\"",
+                                Some(Alignment::Center)=>" style=\"text-align:center\"",
+                                Some(Alignment::Right)=>" style=\"text-align:right\"",
+                                _=>"",
+                            };
+                            let fmt=format!("<{}{}>",tag,align);
+                            out.push_str(&fmt);
                         },
                         CodeBlock(ty)=>{
                             use CodeBlockKind::*;
@@ -256,35 +511,30 @@ impl IntoHtml for Section {
                         Strong=>out.push_str("<strong>"),
                         Strikethrough=>out.push_str("<strike>"),
                         Link(ty,dest,title)=>{
-                            if ty!=LinkType::Inline {
-                                println!("Warning: only inline link type is supported");
-                                continue;
-                            }
-                            let fmt=format!("<a href=\"{}\" title=\"{}\">",&*dest,&*title);
+                            let dest=link_destination(ty,&dest);
+                            let fmt=match self.url(&dest) {
+                                Some(dest)=>format!("<a href=\"{}\" title=\"{}\">",dest,escape(&title)),
+                                None=>format!("<a title=\"{}\">",escape(&title)),
+                            };
                             out.push_str(&fmt);
                         },
                         Image(ty,dest,title)=>{
-                            if ty!=LinkType::Inline {
-                                println!("Warning: only inline link type is supported");
+                            let dest=link_destination(ty,&dest);
+                            let Some(dest)=self.url(&dest) else {
                                 continue;
-                            }
-                            let fmt=format!("<img src=\"{}\" title=\"{}\">",&*dest,&*title);
+                            };
+                            let fmt=format!("<img src=\"{}\" title=\"{}\">",dest,escape(&title));
                             out.push_str(&fmt);
                         },
-                        FootnoteDefinition(name)=>{
-                            in_footnote=true;
-                            let fmt=format!("<p id=\"{}\"><sup>{}</sup>&nbsp;",name,name);
-                            out.push_str(&fmt);
+                        FootnoteDefinition(label)=>{
+                            footnote=Some((label.to_string(),out.len()));
                         },
-                        _=>{},
                     }
                 },
                 End(tag)=>{
                     use Tag::*;
                     match tag {
-                        Paragraph=>{
-                            if !in_footnote{out.push_str("</p>")}
-                        },
+                        Paragraph=>out.push_str("</p>"),
                         BlockQuote=>out.push_str("</blockquote>"),
                         List(start)=>{
                             if start.is_some() {
@@ -295,21 +545,36 @@ impl IntoHtml for Section {
                         },
                         Item=>out.push_str("</li>"),
                         Heading(level,_,_)=>{
-                            use HeadingLevel::*;
-                            match level {
-                                H1=>out.push_str("</h1>"),
-                                H2=>out.push_str("</h2>"),
-                                H3=>out.push_str("</h3>"),
-                                H4=>out.push_str("</h4>"),
-                                H5=>out.push_str("</h5>"),
-                                H6=>out.push_str("</h6>"),
-                            }
+                            let fmt=format!("</{}>",level);
+                            out.push_str(&fmt);
+                        },
+                        Table(_)=>out.push_str("</tbody></table>"),
+                        TableHead=>{
+                            in_table_head=false;
+                            out.push_str("</tr></thead><tbody>");
+                        },
+                        TableRow=>out.push_str("</tr>"),
+                        TableCell=>{
+                            let fmt=if in_table_head {"</th>"} else {"</td>"};
+                            out.push_str(fmt);
+                            table_cell+=1;
                         },
                         CodeBlock(_)=>{
-                            // TODO: code block highlighting
-                            let code=code.take().unwrap();
-                            out.push_str("<pre style=\"white-space:break-spaces\">");
-                            out.push_str(&code.1);
+                            let (lang,code)=code.take().unwrap();
+                            // Info strings like `rust,ignore` or `rust title` only name the language first.
+                            let lang=lang.as_deref()
+                                .and_then(|lang|lang.split(|c|c==','||c==' ').next())
+                                .filter(|lang|lang.len()>0);
+                            let highlighted=lang.and_then(|lang|{
+                                self.highlighter.as_ref()?.highlight(lang,&code)
+                            });
+                            if let Some((pre,html))=highlighted {
+                                out.push_str(&pre);
+                                out.push_str(&html);
+                            } else {
+                                out.push_str("<pre style=\"white-space:break-spaces\">");
+                                out.push_str(&escape(&code));
+                            }
                             out.push_str("</pre>");
                         },
                         Emphasis=>out.push_str("</em>"),
@@ -317,8 +582,10 @@ impl IntoHtml for Section {
                         Strikethrough=>out.push_str("</strike>"),
                         Link(..)=>out.push_str("</a>"),
                         FootnoteDefinition(_)=>{
-                            out.push_str("</p>");
-                            in_footnote=false;
+                            if let Some((label,start))=footnote.take() {
+                                let content=out.split_off(start);
+                                self.footnotes.define(&label,content);
+                            }
                         },
                         _=>{},
                     }
@@ -327,103 +594,150 @@ impl IntoHtml for Section {
                     if let Some((_,code_text))=&mut code {
                         code_text.push_str(&*text);
                     } else {
-                        out.push_str(&*text);
+                        out.push_str(&escape(&text));
                     }
                 },
                 Code(code)=>{
                     out.push_str("<span style=\"font-family:monospace\">");
-                    out.push_str(&*code);
+                    out.push_str(&escape(&code));
                     out.push_str("</span>");
                 },
-                Html(html)=>out.push_str(&*html),
-                FootnoteReference(r)=>{
-                    let fmt=format!("<a href=\"#{}\"><sup>{}</sup></a>",r,r);
+                Html(html)=>{
+                    if !self.safe {
+                        out.push_str(&*html);
+                    }
+                },
+                FootnoteReference(label)=>{
+                    let (number,reference)=self.footnotes.reference(&label);
+                    let fmt=format!("<sup id=\"{}\"><a href=\"#fn-{}\">{}</a></sup>",footnote_reference_id(number,reference),number,number);
                     out.push_str(&fmt)
                 },
+                TaskListMarker(checked)=>{
+                    if checked {
+                        out.push_str("<input type=\"checkbox\" disabled checked> ");
+                    } else {
+                        out.push_str("<input type=\"checkbox\" disabled> ");
+                    }
+                },
                 SoftBreak|HardBreak=>out.push_str("<br>"),
                 Rule=>out.push_str("<hr>"),
-                _=>{},
             }
         }
-        //println!("-------------------------------");
         out.push_str("</div></div>");
-        return out;
+        return writer.write_all(out.as_bytes());
+    }
+    fn toc(&mut self,toc:&Toc,parent_direction:ParentDirection,out:&mut dyn Write)->IoResult<()> {
+        let section=Section {
+            content:self.outline.toc_markdown(toc),
+            style:toc.style.clone(),
+            tags:Vec::new(),
+            location:None,
+        };
+        return self.section(&section,parent_direction,out);
     }
 }
-impl IntoHtml for Item {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        use Item::*;
-        match self {
-            Horizontal{items,style}=>{
-                let mut out=format!("<div style=\"{}align-items:stretch;display:flex;flex-direction:row;\">",style.unwrap_or_default().into_html(ParentDirection::Horizontal));
-                for item in items {
-                    let fmt=item.into_html(ParentDirection::Horizontal);
-                    out.push_str(&fmt);
-                }
-                out.push_str("</div>");
-                return out;
-            },
-            Vertical{items,style}=>{
-                let mut out=format!("<div style=\"{}align-items:stretch;display:flex;flex-direction:column;\">",style.unwrap_or_default().into_html(ParentDirection::Vertical));
-                for item in items {
-                    let fmt=item.into_html(ParentDirection::Vertical);
-                    out.push_str(&fmt);
-                }
-                out.push_str("</div>");
-                return out;
-            },
-            Section(s)=>return s.into_html(parent_direction),
+
+
+/// Escapes text for use in HTML element content and quoted attribute values.
+pub fn escape(s:&str)->String {
+    let mut out=String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&'=>out.push_str("&amp;"),
+            '<'=>out.push_str("&lt;"),
+            '>'=>out.push_str("&gt;"),
+            '"'=>out.push_str("&quot;"),
+            '\''=>out.push_str("&#39;"),
+            _=>out.push(c),
         }
     }
+    return out;
 }
-impl IntoHtml for PageStyle {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        let mut out=String::new();
-        if let Some(page_size)=self.page_size {
-            let fmt=page_size.into_html(parent_direction);
-            out.push_str(&fmt);
-        }
-        if let Some(text_color)=self.text_color {
-            let fmt=format!("color:{};",text_color.into_html(parent_direction));
-            out.push_str(&fmt);
-        }
-        if let Some(background_color)=self.background_color {
-            let fmt=format!("background-color:{};",background_color.into_html(parent_direction));
-            out.push_str(&fmt);
+/// `font` as a CSS font list. Families that are more than words, or were quoted, are written as
+/// CSS strings, so a `;` or `}` in one can't add declarations of its own.
+fn font_family(font:&str)->String {
+    let word=|c:char|c.is_alphanumeric()||c==' '||c=='-'||c=='_';
+    if font.chars().all(|c|word(c)||c==',') {
+        return font.to_string();
+    }
+    return font.split(',').map(|family|{
+        let family=family.trim();
+        let unquoted=family.strip_prefix('"').and_then(|f|f.strip_suffix('"'))
+            .or_else(||family.strip_prefix('\'').and_then(|f|f.strip_suffix('\'')));
+        match unquoted {
+            None if family.chars().all(word)=>family.to_string(),
+            _=>css_string(unquoted.unwrap_or(family)),
         }
-        if let Some(margin)=self.margin {
-            let fmt=margin.into_html(parent_direction).replace("NAME","padding");
-            out.push_str(&fmt);
+    }).collect::<Vec<_>>().join(", ");
+}
+/// `s` as a double quoted CSS string.
+fn css_string(s:&str)->String {
+    let mut out=String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"'|'\\'=>{
+                out.push('\\');
+                out.push(c);
+            },
+            // A line break can't be in a CSS string, so it is escaped as its code point.
+            '\n'=>out.push_str("\\a "),
+            '\r'=>out.push_str("\\d "),
+            _=>out.push(c),
         }
-        out.push_str("margin:0;");
-        return out;
     }
+    out.push('"');
+    return out;
 }
-impl IntoHtml for Page {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        let mut out=format!("<div style=\"{}\" class=\"page\">",self.style.unwrap_or_default().into_html(parent_direction));
-        for item in self.items {
-            let fmt=item.into_html(parent_direction);
-            out.push_str(&fmt);
+fn footnote_reference_id(number:usize,reference:usize)->String {
+    if reference==1 {
+        return format!("fnref-{}",number);
+    }
+    return format!("fnref-{}-{}",number,reference);
+}
+/// Lists footnotes with links back to each of their references.
+fn footnotes_html(footnotes:Vec<Footnote>)->String {
+    let mut out=String::from("<hr><ol>");
+    for footnote in footnotes {
+        let mut back_links=String::new();
+        for reference in 1..=footnote.references {
+            let label=if reference==1 {String::new()} else {format!("<sup>{}</sup>",reference)};
+            let fmt=format!(" <a href=\"#{}\">&#8617;{}</a>",footnote_reference_id(footnote.number,reference),label);
+            back_links.push_str(&fmt);
         }
-        out.push_str("</div>");
-        return out;
+        let mut content=footnote.content;
+        // Keep the back links on the same line as the last paragraph.
+        if content.ends_with("</p>") {
+            content.truncate(content.len()-4);
+            back_links.push_str("</p>");
+        }
+        let fmt=format!("<li id=\"fn-{}\" value=\"{}\">{}{}</li>",footnote.number,footnote.number,content,back_links);
+        out.push_str(&fmt);
     }
+    out.push_str("</ol>");
+    return out;
 }
-impl IntoHtml for Metadata {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        return format!("<title>{}</title><style>html{{height:100%;width:100%}}body{{height:100%;width:100%}}.page{{display:flex;{}}}</style>",self.title,self.page_style.unwrap_or_default().into_html(parent_direction));
+/// Reference style links are already resolved by the markdown parser, so only email autolinks
+/// need their scheme added.
+fn link_destination(ty:LinkType,dest:&str)->String {
+    if ty==LinkType::Email {
+        return format!("mailto:{}",dest);
     }
+    return dest.to_string();
 }
-impl IntoHtml for Document {
-    fn into_html(self,parent_direction:ParentDirection)->String {
-        let mut out=format!("<!DOCTYPE html><html><head>{}</head><body style=\"margin:0;padding:0\">",self.metadata.into_html(parent_direction));
-        for page in self.pages {
-            let fmt=page.into_html(parent_direction);
-            out.push_str(&fmt);
-        }
-        out.push_str("</body></html>");
-        return out;
+/// Allows relative URLs and the schemes that can't run scripts.
+fn is_safe_url(url:&str)->bool {
+    const SAFE_SCHEMES:&[&str]=&["http","https","mailto","tel","ftp"];
+    let url=url.trim();
+    match url.find(':') {
+        Some(colon)=>{
+            let scheme=&url[..colon];
+            // A `:` after a path, query, or fragment starts isn't a scheme separator.
+            if scheme.contains(|c|c=='/'||c=='?'||c=='#') {
+                return true;
+            }
+            return SAFE_SCHEMES.contains(&scheme.to_lowercase().as_str());
+        },
+        None=>true,
     }
 }
 
@@ -434,3 +748,17 @@ pub enum ParentDirection {
     Horizontal,
     None,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_fonts_to_the_font_family() {
+        assert_eq!(font_family("FiraCode Nerd Font, monospace"),"FiraCode Nerd Font, monospace");
+        assert_eq!(font_family("\"Fira Code\", serif"),"\"Fira Code\", serif");
+        assert_eq!(font_family("x;position:fixed}"),"\"x;position:fixed}\"");
+        assert_eq!(font_family("'a\"b\\\\'"),"\"a\\\"b\\\\\\\\\"");
+    }
+}
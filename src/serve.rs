@@ -0,0 +1,263 @@
+use std::{
+    fs::read,
+    io::{
+        BufRead,
+        BufReader,
+        Result as IoResult,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    path::{
+        Component,
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+    },
+    thread::{
+        sleep,
+        spawn,
+    },
+    time::Duration,
+};
+use crate::{
+    parse,
+    profile_document,
+    WATCH_INTERVAL,
+    diagnostic::{
+        self,
+        Diagnostic,
+        Report,
+    },
+    sources::Sources,
+    render::{
+        escape,
+        HtmlRenderer,
+        Renderer,
+    },
+    url::percent_decode,
+    warning::Level,
+    watch::Watcher,
+};
+
+
+/// The path the pages listen to for reloads.
+const RELOAD_PATH:&str="/__reload";
+/// Reloads the page when the server says a rebuild finished.
+const RELOAD_SCRIPT:&str="<script>new EventSource(\"/__reload\").onmessage=()=>location.reload();</script>";
+/// How often an idle reload stream gets a comment, so closed tabs are noticed.
+const KEEP_ALIVE:Duration=Duration::from_secs(15);
+
+
+pub struct ServeOptions {
+    pub port:u16,
+    pub safe:bool,
+    pub profile:Option<String>,
+    pub report:Report,
+}
+/// The page from the last build, and how many builds there have been.
+#[derive(Default)]
+struct Build {
+    html:String,
+    version:u64,
+}
+type Shared=Arc<(Mutex<Build>,Condvar)>;
+
+
+/// Serves the rendered document on localhost and rebuilds it when its inputs change. Open pages
+/// reload after each build, and a failed build shows its error instead of the old output.
+pub fn serve(path:PathBuf,options:ServeOptions)->IoResult<()> {
+    let listener=TcpListener::bind(("127.0.0.1",options.port))?;
+    let shared:Shared=Arc::default();
+    let mut watcher=rebuild(&path,&options,&shared);
+    if !options.report.quiet {
+        eprintln!("Serving `{}` at http://localhost:{}/. Press Ctrl+C to stop",path.display(),options.port);
+    }
+    let root=path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let listener_shared=shared.clone();
+    spawn(move||{
+        for stream in listener.incoming() {
+            let Ok(stream)=stream else {
+                continue;
+            };
+            let shared=listener_shared.clone();
+            let root=root.clone();
+            spawn(move||{
+                // The browser closing a connection isn't worth reporting.
+                let _=respond(stream,&root,&shared);
+            });
+        }
+    });
+    loop {
+        sleep(WATCH_INTERVAL);
+        if watcher.changed() {
+            if !options.report.quiet {
+                eprintln!("Rebuilding `{}`",path.display());
+            }
+            watcher=rebuild(&path,&options,&shared);
+        }
+    }
+}
+/// Builds the page, tells open pages to reload, and returns a watcher for the new inputs.
+fn rebuild(path:&Path,options:&ServeOptions,shared:&Shared)->Watcher {
+    let sources=Sources::default();
+    let (doc,inputs)=parse(path,&sources,&options.report);
+    let mut html=match doc {
+        Ok((doc,mut warnings))=>{
+            let (doc,profile_warnings)=profile_document(&doc,path,options.profile.as_deref());
+            warnings.extend(profile_warnings);
+            let mut out=Vec::new();
+            let mut renderer=if options.safe {HtmlRenderer::safe()} else {HtmlRenderer::default()};
+            let result=renderer.render(&doc,&mut out);
+            warnings.extend(renderer.take_warnings());
+            // Like `build`, a page with denied warnings isn't shown.
+            if options.report.warnings(&warnings,&sources) {
+                let message=warnings.iter()
+                    .filter(|warning|options.report.levels.level(&warning.kind)==Level::Deny)
+                    .map(|warning|diagnostic::render(&Diagnostic::warning(warning,Level::Deny,&sources),false))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                error_page(&message)
+            } else {
+                match result {
+                    Ok(())=>String::from_utf8_lossy(&out).into_owned(),
+                    Err(e)=>error_page(&e.to_string()),
+                }
+            }
+        },
+        Err(message)=>error_page(&message),
+    };
+    match html.rfind("</body>") {
+        Some(i)=>html.insert_str(i,RELOAD_SCRIPT),
+        None=>html.push_str(RELOAD_SCRIPT),
+    }
+    let (build,changed)=&**shared;
+    let mut build=build.lock().unwrap();
+    build.html=html;
+    build.version+=1;
+    changed.notify_all();
+    return Watcher::new(inputs);
+}
+fn error_page(message:&str)->String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Build failed</title><style>{}</style></head><body><div class=\"overlay\"><h1>Build failed</h1><pre>{}</pre><p>The page reloads when the error is fixed.</p></div></body></html>",
+        "body{margin:0;background:#222;font-family:sans-serif}.overlay{margin:2em;padding:1em 2em;background:#fff;border-left:6px solid #c00}pre{white-space:pre-wrap;color:#c00;font-size:1.1em}",
+        escape(message),
+    )
+}
+fn respond(mut stream:TcpStream,root:&Path,shared:&Shared)->IoResult<()> {
+    let mut reader=BufReader::new(stream.try_clone()?);
+    let mut request_line=String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, which don't change the response.
+    let mut header=String::new();
+    while reader.read_line(&mut header)?>2 {
+        header.clear();
+    }
+    let mut parts=request_line.split_whitespace();
+    let method=parts.next().unwrap_or("");
+    let target=parts.next().unwrap_or("/");
+    let target=target.split(['?','#']).next().unwrap_or("/");
+    if method!="GET" {
+        return write_response(&mut stream,"405 Method Not Allowed","text/plain",b"Method not allowed");
+    }
+    if target=="/"||target=="/index.html" {
+        let html=shared.0.lock().unwrap().html.clone();
+        return write_response(&mut stream,"200 OK","text/html; charset=utf-8",html.as_bytes());
+    } else if target==RELOAD_PATH {
+        return reload_stream(stream,shared);
+    }
+    let Some(file)=asset_path(root,target) else {
+        return write_response(&mut stream,"404 Not Found","text/plain",b"Not found");
+    };
+    match read(&file) {
+        Ok(contents)=>write_response(&mut stream,"200 OK",content_type(&file),&contents),
+        Err(_)=>write_response(&mut stream,"404 Not Found","text/plain",b"Not found"),
+    }
+}
+/// Sends a server-sent event after each build until the page goes away.
+fn reload_stream(mut stream:TcpStream,shared:&Shared)->IoResult<()> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n")?;
+    let (build,changed)=&**shared;
+    let mut version=build.lock().unwrap().version;
+    loop {
+        let guard=build.lock().unwrap();
+        let (guard,_)=changed.wait_timeout_while(guard,KEEP_ALIVE,|build|build.version==version).unwrap();
+        if guard.version!=version {
+            version=guard.version;
+            drop(guard);
+            stream.write_all(b"data: reload\n\n")?;
+        } else {
+            drop(guard);
+            stream.write_all(b": keep-alive\n\n")?;
+        }
+        stream.flush()?;
+    }
+}
+/// The file a request is for, relative to the document. Paths that leave the document's folder
+/// aren't served.
+fn asset_path(root:&Path,target:&str)->Option<PathBuf> {
+    // Checked after decoding, since `%2F` and `%2e%2e` decode to a `/` and a `..`.
+    let decoded=percent_decode(target.trim_start_matches('/'))?;
+    let relative=Path::new(&decoded);
+    if !relative.components().all(|c|matches!(c,Component::Normal(_))) {
+        return None;
+    }
+    let mut path=root.join(relative);
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    return Some(path);
+}
+fn content_type(path:&Path)->&'static str {
+    let extension=path.extension().and_then(|e|e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html"|"htm"=>"text/html; charset=utf-8",
+        "css"=>"text/css",
+        "js"=>"text/javascript",
+        "png"=>"image/png",
+        "jpg"|"jpeg"=>"image/jpeg",
+        "gif"=>"image/gif",
+        "svg"=>"image/svg+xml",
+        "webp"=>"image/webp",
+        "ico"=>"image/x-icon",
+        "pdf"=>"application/pdf",
+        "woff"=>"font/woff",
+        "woff2"=>"font/woff2",
+        "ttf"=>"font/ttf",
+        _=>"application/octet-stream",
+    }
+}
+fn write_response(stream:&mut TcpStream,status:&str,content_type:&str,body:&[u8])->IoResult<()> {
+    write!(stream,"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",status,content_type,body.len())?;
+    stream.write_all(body)?;
+    return stream.flush();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_assets_in_the_documents_folder() {
+        let root=Path::new("/srv/doc");
+        assert_eq!(asset_path(root,"/images/a%20b.png"),Some(root.join("images/a b.png")));
+        assert_eq!(asset_path(root,"//images/a.png"),Some(root.join("images/a.png")));
+    }
+
+    #[test]
+    fn doesnt_serve_paths_outside_the_documents_folder() {
+        let root=Path::new("/srv/doc");
+        assert_eq!(asset_path(root,"/%2Fetc%2Fpasswd"),None);
+        assert_eq!(asset_path(root,"/%2e%2e/secret"),None);
+        assert_eq!(asset_path(root,"/images/%2E%2E%2F%2E%2E%2Fsecret"),None);
+        assert_eq!(asset_path(root,"/../secret"),None);
+    }
+}
@@ -0,0 +1,46 @@
+use elsa::FrozenVec;
+use std::{
+    fs::read_to_string,
+    io::Result as IoResult,
+    path::Path,
+};
+
+
+/// Owns the text of every file read for a document.
+///
+/// Included files are only found while parsing, so they are kept here where the document and its
+/// errors can borrow them for as long as the `Sources` lives.
+#[derive(Default)]
+pub struct Sources {
+    names:FrozenVec<String>,
+    contents:FrozenVec<String>,
+}
+impl Sources {
+    /// Reads a file and returns its name and contents.
+    pub fn load(&self,path:&Path)->IoResult<(&str,&str)> {
+        let contents=read_to_string(path)?;
+        return Ok(self.add(path.to_string_lossy().into_owned(),contents));
+    }
+    /// Adds text that was generated rather than read, like an expanded component.
+    pub fn add(&self,name:String,contents:String)->(&str,&str) {
+        let name=self.names.push_get(name);
+        let contents=self.contents.push_get(contents);
+        return (name,contents);
+    }
+    /// The contents of a file that was read. Generated text under the same name, like an expanded
+    /// component, is passed over for the file itself.
+    pub fn get(&self,name:&str)->Option<&str> {
+        let i=self.names.iter().position(|n|n==name)?;
+        return self.contents.get(i);
+    }
+    /// The name of every file that was read, without duplicates.
+    pub fn files(&self)->Vec<&str> {
+        let mut files=Vec::new();
+        for name in self.names.iter() {
+            if !files.contains(&name) {
+                files.push(name);
+            }
+        }
+        return files;
+    }
+}
@@ -0,0 +1,460 @@
+use std::ops::Range;
+
+
+/// Keys whose value is the rest of the line, which can hold braces.
+const TEXT_KEYS:&[&str]=&["title","code_theme","use","extends","font"];
+
+
+/// The blocks of a file and the lines in them, found without parsing any values.
+///
+/// This is what the language server and the formatter work on, since they need to know where
+/// things are even in documents that don't parse.
+pub struct SyntaxTree<'a> {
+    pub nodes:Vec<Node<'a>>,
+    /// Where each `}` that has no block to close is.
+    pub unmatched:Vec<usize>,
+}
+pub enum Node<'a> {
+    /// A line that isn't a block, like `width: 5in`, and where it starts.
+    Line(&'a str,usize),
+    Block(Block<'a>),
+    /// A `//` or `/* */` comment, where it starts, and whether it is after something on its line.
+    Comment(&'a str,usize,bool),
+    /// One or more blank lines.
+    Blank,
+}
+pub struct Block<'a> {
+    pub kind:BlockKind,
+    /// What comes before the `{`, like `style sidebar`.
+    pub header:&'a str,
+    /// Where the header starts, where the `{` is, and where the `}` is. The `}` is [`None`] when
+    /// the file ends first.
+    pub start:usize,
+    pub open:usize,
+    pub end:Option<usize>,
+    pub body:Body<'a>,
+}
+pub enum Body<'a> {
+    Nodes(Vec<Node<'a>>),
+    /// The lines of a content block without the first one's indentation, like the parser reads
+    /// them.
+    Content(Vec<&'a str>),
+}
+/// What a block is, from its header and the block it is in.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum BlockKind {
+    Metadata,
+    PageStyle,
+    /// A `style NAME` in the metadata.
+    NamedStyle,
+    SectionStyle,
+    PageSize,
+    Markdown,
+    Vars,
+    Page,
+    Vertical,
+    Horizontal,
+    Section,
+    Content,
+    Toc,
+    Component,
+    For,
+    Other,
+}
+impl BlockKind {
+    fn new(header:&str,parent:Option<BlockKind>)->Self {
+        use BlockKind::*;
+        let word=header.split([' ','(']).next().unwrap_or("");
+        match word {
+            "metadata"=>Metadata,
+            "page"=>Page,
+            "vertical"=>Vertical,
+            "horizontal"=>Horizontal,
+            "section"=>Section,
+            "content"=>Content,
+            "toc"=>Toc,
+            "markdown"=>Markdown,
+            "vars"=>Vars,
+            "page_size"=>PageSize,
+            "component"=>Component,
+            "for"=>For,
+            "style" if parent==Some(Metadata)&&header!="style"=>NamedStyle,
+            "style" if parent==Some(Metadata)||parent==Some(Page)=>PageStyle,
+            "style"=>SectionStyle,
+            _=>Other,
+        }
+    }
+}
+impl<'a> SyntaxTree<'a> {
+    pub fn new(source:&'a str)->Self {
+        let mut scanner=Scanner{source,pos:0};
+        let mut nodes=Vec::new();
+        let mut unmatched=Vec::new();
+        loop {
+            nodes.extend(scanner.nodes(None));
+            if scanner.pos>=source.len() {
+                break;
+            }
+            // Only a `}` stops the top level early.
+            unmatched.push(scanner.pos);
+            scanner.pos+=1;
+        }
+        return SyntaxTree{nodes,unmatched};
+    }
+    /// Whether every block is closed and every `}` closes one.
+    pub fn is_balanced(&self)->bool {
+        fn closed(nodes:&[Node])->bool {
+            nodes.iter().all(|node|match node {
+                Node::Block(block)=>block.end.is_some()&&match &block.body {
+                    Body::Nodes(nodes)=>closed(nodes),
+                    Body::Content(_)=>true,
+                },
+                _=>true,
+            })
+        }
+        return self.unmatched.is_empty()&&closed(&self.nodes);
+    }
+    /// Every comment and where it starts, in order.
+    pub fn comments(&self)->Vec<(&'a str,usize)> {
+        fn visit<'a>(nodes:&[Node<'a>],comments:&mut Vec<(&'a str,usize)>) {
+            for node in nodes {
+                match node {
+                    Node::Comment(text,start,_)=>comments.push((text,*start)),
+                    Node::Block(Block{body:Body::Nodes(children),..})=>visit(children,comments),
+                    _=>{},
+                }
+            }
+        }
+        let mut comments=Vec::new();
+        visit(&self.nodes,&mut comments);
+        return comments;
+    }
+    /// The blocks `offset` is in, from the outermost one in. A block's header counts as in it.
+    pub fn path(&self,offset:usize)->Vec<&Block<'a>> {
+        let mut path=Vec::new();
+        let mut nodes=&self.nodes;
+        'outer: loop {
+            for node in nodes.iter() {
+                let Node::Block(block)=node else {
+                    continue;
+                };
+                if block.start<=offset&&block.end.is_none_or(|end|offset<=end) {
+                    path.push(block);
+                    match &block.body {
+                        Body::Nodes(children)=>{
+                            nodes=children;
+                            continue 'outer;
+                        },
+                        Body::Content(_)=>break 'outer,
+                    }
+                }
+            }
+            break;
+        }
+        return path;
+    }
+    /// Every block, outer ones before the blocks in them.
+    pub fn blocks(&self)->Vec<&Block<'a>> {
+        fn visit<'t,'a>(nodes:&'t [Node<'a>],blocks:&mut Vec<&'t Block<'a>>) {
+            for node in nodes {
+                if let Node::Block(block)=node {
+                    blocks.push(block);
+                    if let Body::Nodes(children)=&block.body {
+                        visit(children,blocks);
+                    }
+                }
+            }
+        }
+        let mut blocks=Vec::new();
+        visit(&self.nodes,&mut blocks);
+        return blocks;
+    }
+    /// Where each value that is the rest of its line is, like the `My title` of `title: My title`.
+    /// The parser reads a `$` in them as a variable.
+    pub fn text_values(&self)->Vec<Range<usize>> {
+        fn visit(nodes:&[Node],parent:Option<BlockKind>,values:&mut Vec<Range<usize>>) {
+            for node in nodes {
+                match node {
+                    Node::Line(text,start) if is_text_key(text,parent)=>values.push(start+key(text).len()+1..start+text.len()),
+                    Node::Block(Block{kind,body:Body::Nodes(children),..})=>visit(children,Some(*kind),values),
+                    _=>{},
+                }
+            }
+        }
+        let mut values=Vec::new();
+        visit(&self.nodes,None,&mut values);
+        return values;
+    }
+}
+impl Block<'_> {
+    /// The first word of each line and block header in the block, which is the key it sets, and
+    /// where it is.
+    pub fn keys(&self)->Vec<(&str,usize)> {
+        let Body::Nodes(nodes)=&self.body else {
+            return Vec::new();
+        };
+        return nodes.iter().filter_map(|node|match node {
+            Node::Line(text,start)=>Some((key(text),*start)),
+            Node::Block(block)=>Some((key(block.header),block.start)),
+            Node::Comment(..)|Node::Blank=>None,
+        }).collect();
+    }
+}
+/// The key a line or block header starts with.
+pub fn key(text:&str)->&str {
+    let end=text.find([' ',':','(','{']).unwrap_or(text.len());
+    return &text[..end];
+}
+/// Whether `text` starts with a key whose value is the rest of the line.
+fn is_text_key(text:&str,parent:Option<BlockKind>)->bool {
+    let key=key(text);
+    return text[key.len()..].starts_with(':')&&(TEXT_KEYS.contains(&key)||parent==Some(BlockKind::Vars));
+}
+
+
+struct Scanner<'a> {
+    source:&'a str,
+    pos:usize,
+}
+impl<'a> Scanner<'a> {
+    fn rest(&self)->&'a str {
+        &self.source[self.pos..]
+    }
+    /// The lines and blocks up to the `}` that ends the block they are in, or the end of the file.
+    fn nodes(&mut self,parent:Option<BlockKind>)->Vec<Node<'a>> {
+        let mut nodes=Vec::new();
+        loop {
+            let newlines=self.skip_whitespace();
+            if self.pos>=self.source.len()||self.rest().starts_with('}') {
+                return nodes;
+            }
+            if newlines>1&&!nodes.is_empty() {
+                nodes.push(Node::Blank);
+            }
+            let start=self.pos;
+            if let Some(end)=self.comment_end() {
+                let line_start=self.source[..start].rfind('\n').map_or(0,|i|i+1);
+                let trailing=!self.source[line_start..start].trim().is_empty();
+                self.pos=end;
+                nodes.push(Node::Comment(self.source[start..end].trim_end(),start,trailing));
+                continue;
+            }
+            self.pos=self.statement_end(parent);
+            let text=self.source[start..self.pos].trim_end();
+            if !self.rest().starts_with('{') {
+                if !text.is_empty() {
+                    nodes.push(Node::Line(text,start));
+                }
+                continue;
+            }
+            let open=self.pos;
+            self.pos+=1;
+            let kind=BlockKind::new(text,parent);
+            let body=match kind {
+                BlockKind::Content=>Body::Content(self.content()),
+                _=>Body::Nodes(self.nodes(Some(kind))),
+            };
+            let end=self.rest().starts_with('}').then_some(self.pos);
+            if end.is_some() {
+                self.pos+=1;
+            }
+            nodes.push(Node::Block(Block{kind,header:text,start,open,end,body}));
+        }
+    }
+    /// Skips spaces and line breaks, and returns how many line breaks there were.
+    fn skip_whitespace(&mut self)->usize {
+        let rest=self.rest();
+        let skipped=&rest[..rest.len()-rest.trim_start().len()];
+        self.pos+=skipped.len();
+        return skipped.matches('\n').count();
+    }
+    /// Where the comment at the current position ends, if there is one. A `//` comment ends at the
+    /// end of its line, and a `/* */` comment that isn't closed ends at the end of the file.
+    fn comment_end(&self)->Option<usize> {
+        let rest=self.rest();
+        if rest.starts_with("//") {
+            return Some(self.pos+rest.find('\n').unwrap_or(rest.len()));
+        }
+        let body=rest.strip_prefix("/*")?;
+        return Some(self.pos+body.find("*/").map_or(rest.len(),|i|i+4));
+    }
+    /// Where the line or block header at the current position ends: at the end of the line, at a
+    /// `{` or `}` outside of strings and parentheses, or at a comment that goes to the end of the
+    /// line. Text values take the whole line, up to a comment after a space.
+    fn statement_end(&self,parent:Option<BlockKind>)->usize {
+        let rest=self.rest();
+        let line_end=rest.find('\n').unwrap_or(rest.len());
+        if is_text_key(rest,parent) {
+            let comment=rest[..line_end].find(" //").into_iter().chain(rest[..line_end].find(" /*")).min();
+            return self.pos+comment.unwrap_or(line_end);
+        }
+        let mut depth=0;
+        let mut string=false;
+        let mut escaped=false;
+        for (i,c) in rest.char_indices() {
+            match c {
+                '\n'=>return self.pos+i,
+                _ if escaped=>escaped=false,
+                '\\' if string=>escaped=true,
+                '"'=>string^=true,
+                _ if string=>{},
+                '/' if self.ends_line(self.pos+i)=>return self.pos+i,
+                '('=>depth+=1,
+                ')' if depth>0=>depth-=1,
+                '{'|'}' if depth==0=>return self.pos+i,
+                _=>{},
+            }
+        }
+        return self.source.len();
+    }
+    /// Whether a comment starts at `offset` and nothing but spaces come after it on the line it
+    /// ends on. Comments between the words of a line are left in it.
+    fn ends_line(&self,offset:usize)->bool {
+        let scanner=Scanner{source:self.source,pos:offset};
+        let Some(end)=scanner.comment_end() else {
+            return false;
+        };
+        let after=&self.source[end..];
+        return after[..after.find('\n').unwrap_or(after.len())].trim().is_empty();
+    }
+    /// The lines of a content block whose `{` was just passed, the way the parser reads them: they
+    /// end at the first line that isn't blank and isn't indented as much as the first one.
+    fn content(&mut self)->Vec<&'a str> {
+        while self.rest().starts_with('\n')||self.rest().starts_with("\r\n") {
+            self.pos+=if self.rest().starts_with('\n') {1} else {2};
+        }
+        let rest=self.rest();
+        let indent=&rest[..rest.len()-rest.trim_start_matches(' ').len()];
+        self.pos+=indent.len();
+        let mut lines=vec![self.line()];
+        while self.pos<self.source.len() {
+            let rest=self.rest();
+            if rest.starts_with(indent) {
+                self.pos+=indent.len();
+                lines.push(self.line());
+                continue;
+            }
+            let blank=rest.trim_start_matches(' ');
+            if blank.starts_with('\n')||blank.starts_with("\r\n") {
+                self.pos+=rest.len()-blank.len();
+                self.line();
+                lines.push("");
+                continue;
+            }
+            break;
+        }
+        self.pos+=self.rest().len()-self.rest().trim_start_matches(' ').len();
+        return lines;
+    }
+    /// The rest of the line, without its line break, which is skipped.
+    fn line(&mut self)->&'a str {
+        let rest=self.rest();
+        let (line,len)=match rest.find('\n') {
+            Some(i)=>(&rest[..i],i+1),
+            None=>(rest,rest.len()),
+        };
+        self.pos+=len;
+        return line.strip_suffix('\r').unwrap_or(line);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What each node is, as `line:`, `block:`, `comment:`, `after:` for a comment after something
+    /// on its line, or `blank`, with the blocks' nodes indented.
+    fn outline(nodes:&[Node],depth:usize,out:&mut Vec<String>) {
+        let indent="  ".repeat(depth);
+        for node in nodes {
+            match node {
+                Node::Line(text,_)=>out.push(format!("{}line:{}",indent,text)),
+                Node::Comment(text,_,trailing)=>out.push(format!("{}{}:{}",indent,if *trailing {"after"} else {"comment"},text)),
+                Node::Blank=>out.push(format!("{}blank",indent)),
+                Node::Block(block)=>{
+                    out.push(format!("{}block:{}",indent,block.header));
+                    match &block.body {
+                        Body::Nodes(children)=>outline(children,depth+1,out),
+                        Body::Content(lines)=>out.extend(lines.iter().map(|line|format!("{}  |{}",indent,line))),
+                    }
+                },
+            }
+        }
+    }
+    fn nodes(source:&str)->Vec<String> {
+        let mut out=Vec::new();
+        outline(&SyntaxTree::new(source).nodes,0,&mut out);
+        return out;
+    }
+
+    #[test]
+    fn text_values_end_at_a_comment_after_a_space() {
+        assert_eq!(nodes("metadata {\n    title: a // b\n    font: a{b}/* c */\n    extends: a /* b */\n}"),[
+            "block:metadata",
+            "  line:title: a",
+            "  after:// b",
+            "  line:font: a{b}/* c */",
+            "  line:extends: a",
+            "  after:/* b */",
+        ]);
+    }
+
+    #[test]
+    fn lines_end_at_braces_outside_strings_and_parentheses() {
+        assert_eq!(nodes("card(\"a \\\" } b\", f(x)) { width: 5in }\nkey: value // note"),[
+            "block:card(\"a \\\" } b\", f(x))",
+            "  line:width: 5in",
+            "line:key: value",
+            "after:// note",
+        ]);
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        assert_eq!(nodes("page { // head\n    /* one\n    two */\n    width: /* inline */ 5in\n\n\n    height: 1in\n}"),[
+            "block:page",
+            "  after:// head",
+            "  comment:/* one\n    two */",
+            "  line:width: /* inline */ 5in",
+            "  blank",
+            "  line:height: 1in",
+        ]);
+    }
+
+    #[test]
+    fn reads_content_blocks_like_the_parser() {
+        assert_eq!(nodes("section {\n    content {\n        # Title\n\n          Code\n        Text\n    }\n}"),[
+            "block:section",
+            "  block:content",
+            "    |# Title",
+            "    |",
+            "    |  Code",
+            "    |Text",
+        ]);
+    }
+
+    #[test]
+    fn finds_block_kinds_from_their_parent() {
+        use BlockKind::*;
+        let tree=SyntaxTree::new("metadata { style { } style sidebar { } }\npage { style { } section { style { } } }");
+        let kinds=tree.blocks().iter().map(|block|block.kind).collect::<Vec<_>>();
+        assert_eq!(kinds,[Metadata,PageStyle,NamedStyle,Page,PageStyle,Section,SectionStyle]);
+    }
+
+    #[test]
+    fn finds_unbalanced_braces() {
+        assert!(SyntaxTree::new("page { section { } }").is_balanced());
+        assert!(!SyntaxTree::new("page { section { }").is_balanced());
+        let tree=SyntaxTree::new("page { }\n}");
+        assert!(!tree.is_balanced());
+        assert_eq!(tree.unmatched,[9]);
+    }
+
+    #[test]
+    fn finds_text_values() {
+        let source="metadata {\n    title: $a // b\n    vars {\n        c: $d\n    }\n}\nsection {\n    width: $e\n}";
+        let values=SyntaxTree::new(source).text_values().into_iter().map(|range|&source[range]).collect::<Vec<_>>();
+        assert_eq!(values,[" $a"," $d"]);
+    }
+}
@@ -0,0 +1,18 @@
+/// Decodes the `%XX` escapes of a URL path. [`None`] when an escape is cut short or the result
+/// isn't UTF-8.
+pub fn percent_decode(s:&str)->Option<String> {
+    let bytes=s.as_bytes();
+    let mut out=Vec::new();
+    let mut i=0;
+    while i<bytes.len() {
+        if bytes[i]==b'%' {
+            let hex=s.get(i+1..i+3)?;
+            out.push(u8::from_str_radix(hex,16).ok()?);
+            i+=3;
+        } else {
+            out.push(bytes[i]);
+            i+=1;
+        }
+    }
+    return String::from_utf8(out).ok();
+}
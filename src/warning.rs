@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    fmt::{
+        Display,
+        Formatter,
+        Result as FmtResult,
+    },
+};
+use crate::diagnostic::did_you_mean;
+
+
+/// The name of every kind of warning, for `--allow` and `--deny`.
+pub const WARNING_NAMES:&[&str]=&[
+    "undefined-footnote",
+    "unused-style",
+    "unused-component",
+    "empty-profile",
+    "unsupported-image",
+];
+
+
+/// Where something is in the document's source.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Location {
+    pub file:String,
+    /// The byte offset in the file.
+    pub offset:usize,
+}
+/// A problem that doesn't stop the document from building, unless it's denied.
+#[derive(Debug,Clone)]
+pub struct Warning {
+    pub kind:WarningKind,
+    pub location:Option<Location>,
+}
+#[derive(Debug,Clone)]
+pub enum WarningKind {
+    UndefinedFootnote(String),
+    UnusedStyle(String),
+    UnusedComponent(String),
+    /// A profile, and the file nothing in is tagged with it.
+    EmptyProfile(String,String),
+    /// An image's destination, which the PDF renderer leaves out.
+    UnsupportedImage(String),
+}
+impl WarningKind {
+    /// The name used to allow or deny it, which is one of [`WARNING_NAMES`].
+    pub fn name(&self)->&'static str {
+        use WarningKind::*;
+        match self {
+            UndefinedFootnote(_)=>"undefined-footnote",
+            UnusedStyle(_)=>"unused-style",
+            UnusedComponent(_)=>"unused-component",
+            EmptyProfile(..)=>"empty-profile",
+            UnsupportedImage(_)=>"unsupported-image",
+        }
+    }
+}
+impl Display for WarningKind {
+    fn fmt(&self,f:&mut Formatter)->FmtResult {
+        use WarningKind::*;
+        match self {
+            UndefinedFootnote(label)=>write!(f,"Footnote `{}` is referenced but never defined",label),
+            UnusedStyle(name)=>write!(f,"Style `{}` is never used",name),
+            UnusedComponent(name)=>write!(f,"Component `{}` is never used",name),
+            EmptyProfile(profile,file)=>write!(f,"Nothing in `{}` is tagged `{}`",file,profile),
+            UnsupportedImage(dest)=>write!(f,"Image `{}` is left out, because images are not supported in PDF output",dest),
+        }
+    }
+}
+
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Level {
+    /// Not reported.
+    Allow,
+    /// Reported, but the build still succeeds.
+    Warn,
+    /// Reported as an error, and the build fails.
+    Deny,
+}
+/// What to do with each kind of warning, from `--allow`, `--deny`, and `--deny-warnings`.
+#[derive(Clone,Default)]
+pub struct Levels {
+    deny_warnings:bool,
+    levels:HashMap<&'static str,Level>,
+}
+impl Levels {
+    /// Sets the level of a kind of warning by name. The last one given for a name wins, and
+    /// they take precedence over `--deny-warnings`.
+    pub fn set(&mut self,name:&str,level:Level)->Result<(),String> {
+        let Some(name)=WARNING_NAMES.iter().find(|n|**n==name) else {
+            let help=did_you_mean(name,WARNING_NAMES.iter().copied())
+                .map(|help|format!(": {}",help))
+                .unwrap_or_else(||format!(". Expected one of: {}",WARNING_NAMES.join(", ")));
+            return Err(format!("Unknown warning `{}`{}",name,help));
+        };
+        self.levels.insert(name,level);
+        return Ok(());
+    }
+    pub fn deny_warnings(&mut self) {
+        self.deny_warnings=true;
+    }
+    pub fn level(&self,kind:&WarningKind)->Level {
+        match self.levels.get(kind.name()) {
+            Some(level)=>*level,
+            None if self.deny_warnings=>Level::Deny,
+            None=>Level::Warn,
+        }
+    }
+}
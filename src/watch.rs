@@ -0,0 +1,40 @@
+use std::{
+    fs::metadata,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+
+/// Notices when any of a build's input files changes, by checking their modification times.
+///
+/// Polling keeps this working the same everywhere, including for editors that save by replacing
+/// the file.
+pub struct Watcher {
+    files:Vec<(PathBuf,Option<SystemTime>)>,
+}
+impl Watcher {
+    pub fn new(files:Vec<PathBuf>)->Self {
+        let files=files.into_iter()
+            .map(|path|{
+                let modified=modified(&path);
+                (path,modified)
+            })
+            .collect();
+        return Watcher{files};
+    }
+    /// Whether any file was changed, created, or deleted since the last call.
+    pub fn changed(&mut self)->bool {
+        let mut changed=false;
+        for (path,last_modified) in self.files.iter_mut() {
+            let modified=modified(path);
+            if modified!=*last_modified {
+                *last_modified=modified;
+                changed=true;
+            }
+        }
+        return changed;
+    }
+}
+fn modified(path:&PathBuf)->Option<SystemTime> {
+    metadata(path).and_then(|m|m.modified()).ok()
+}